@@ -1,71 +1,504 @@
 use std::collections::{HashMap, HashSet};
+use std::{env, fmt};
 
 use crate::{
-    emerald::{ProductType, SubscriptionApiItem, PRODUCT_CODES},
+    carryover::PriorLinks,
+    catalog::ProductCatalog,
+    emerald::{ProductType, ServiceMedium},
     fcc::FccRecord,
+    index::{AddressKey, FccIndex, NEAREST_METERS},
+    normalize::NormalizedAddress,
+    overrides::Overrides,
+    subscriber::{CustomerClass, Subscriber},
+    GEOCODER, THREAD_POOL,
 };
+use dotenvy::dotenv;
 use fuzzywuzzy::fuzz;
+use rayon::prelude::*;
+use serde::Serialize;
 
-fn digits(text: String) -> String {
-    text.chars().filter(|c| c.is_ascii_digit()).collect()
+// Street similarity scores are fuzz::ratio percentages. A candidate at or
+// above the threshold is linked; one that falls short but is still within
+// the review band is written out for a person to confirm (by adding an
+// override) rather than being silently accepted or dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchConfig {
+    pub threshold: u8,
+    pub review_threshold: u8,
 }
 
-pub fn link(
-    fcc: Vec<FccRecord>,
-    emerald: Vec<SubscriptionApiItem>,
-) -> Vec<(FccRecord, SubscriptionApiItem)> {
-    emerald
-        .iter()
-        .filter_map(|x| {
-            let mut linked: Option<(FccRecord, SubscriptionApiItem)> = None;
-
-            for y in fcc.clone() {
-                if let (
-                    Some(emerald_address),
-                    Some(emerald_city_state_zip),
-                    Some(fcc_address),
-                    Some(fcc_city),
-                ) = (
-                    x.customer.cf_service_address.clone(),
-                    x.customer.cf_service_city_st_zip.clone(),
-                    y.address_primary.clone(),
-                    y.city.clone(),
-                ) {
-                    if let (
-                        Some((emerald_numbers, emerald_street)),
-                        Some((emerald_city, _emerald_other)),
-                        Some((fcc_numbers, fcc_street)),
-                    ) = (
-                        // isolate the initial emerald address numbers
-                        emerald_address.clone().split_once(' '),
-                        // isolate the city from the emerald complex string
-                        emerald_city_state_zip.split_once(','),
-                        // isolate the initial fcc address numbers
-                        fcc_address.clone().split_once(' '),
-                    ) {
-                        // check if the city fields match
-                        if emerald_city.to_uppercase() == fcc_city.to_uppercase()
-                            // extract all the digits and compare (to minimize false positives in the fuzzy matching)
-                            && digits(emerald_address.clone()) == digits(fcc_address.clone())
-                            // check if the address numbers match exactly
-                            && emerald_numbers == fcc_numbers
-                            // check if the street names match roughly
-                            && fuzz::ratio(
-                                &emerald_street.to_uppercase(),
-                                &fcc_street.to_uppercase(),
-                            ) > 80
-                        {
-                            linked = Some((y, x.clone()));
-                            log::debug!("MATCH: {} => {}", emerald_address, fcc_address);
-                            break;
-                        }
-                    }
-                }
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            threshold: 81,
+            review_threshold: 70,
+        }
+    }
+}
+
+impl MatchConfig {
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        let default = MatchConfig::default();
+        let read = |name: &str, default: u8| match env::var(name) {
+            Ok(value) => value.parse().unwrap_or_else(|_| {
+                log::warn!("ignoring invalid {name} {value:?}");
+                default
+            }),
+            Err(_) => default,
+        };
+
+        let threshold = read("MATCH_THRESHOLD", default.threshold);
+        let review_threshold = read("MATCH_REVIEW_THRESHOLD", default.review_threshold);
+
+        MatchConfig {
+            threshold,
+            review_threshold: review_threshold.min(threshold),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UnmatchedReason {
+    MissingAddress,
+    NoCityMatch,
+    DigitMismatch,
+    BelowThreshold {
+        score: u8,
+        candidate: Box<FccRecord>,
+    },
+}
+
+impl fmt::Display for UnmatchedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnmatchedReason::MissingAddress => write!(f, "missing address"),
+            UnmatchedReason::NoCityMatch => write!(f, "no city match"),
+            UnmatchedReason::DigitMismatch => write!(f, "digit mismatch"),
+            UnmatchedReason::BelowThreshold { .. } => write!(f, "below threshold"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Unmatched {
+    pub subscriber: Subscriber,
+    pub reason: UnmatchedReason,
+}
+
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub subscription_id: Option<String>,
+    pub customer_id: Option<String>,
+    pub message: String,
+}
+
+// The strategy that produced a link, from most to least trustworthy.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchTier {
+    Override,
+    Carryover,
+    Exact,
+    Fuzzy,
+    CensusBlock,
+    Nearest,
+}
+
+impl fmt::Display for MatchTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchTier::Override => write!(f, "override"),
+            MatchTier::Carryover => write!(f, "carryover"),
+            MatchTier::Exact => write!(f, "exact"),
+            MatchTier::Fuzzy => write!(f, "fuzzy"),
+            MatchTier::CensusBlock => write!(f, "census_block"),
+            MatchTier::Nearest => write!(f, "nearest"),
+        }
+    }
+}
+
+// How well the two sides of a link agree. unit_match is None when the
+// Fabric record has no secondary unit to compare against.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize)]
+pub struct MatchScore {
+    pub street_ratio: u8,
+    pub city_match: bool,
+    pub house_number_match: bool,
+    pub digits_match: bool,
+    pub unit_match: Option<bool>,
+    pub tier: MatchTier,
+}
+
+impl MatchScore {
+    pub fn new(fcc: &FccRecord, subscriber: &Subscriber, tier: MatchTier) -> Self {
+        let fcc_address = fcc.normalized_address();
+        let emerald_address = subscriber.normalized_service_address();
+
+        let (street_ratio, house_number_match, digits_match, unit_match) =
+            match (&fcc_address, &emerald_address) {
+                (Some(fcc_address), Some(emerald_address)) => (
+                    fuzz::ratio(&emerald_address.street, &fcc_address.street),
+                    emerald_address.number == fcc_address.number,
+                    emerald_address.digits() == fcc_address.digits(),
+                    fcc_address
+                        .unit
+                        .as_ref()
+                        .map(|unit| emerald_address.unit.as_ref() == Some(unit)),
+                ),
+                _ => (0, false, false, None),
+            };
+
+        MatchScore {
+            street_ratio,
+            city_match: fcc.normalized_city().is_some()
+                && fcc.normalized_city() == subscriber.normalized_service_city(),
+            house_number_match,
+            digits_match,
+            unit_match,
+            tier,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub fcc: FccRecord,
+    pub subscriber: Subscriber,
+    pub score: MatchScore,
+}
+
+impl Link {
+    pub fn new(fcc: FccRecord, subscriber: Subscriber, tier: MatchTier) -> Self {
+        let score = MatchScore::new(&fcc, &subscriber, tier);
+
+        Link {
+            fcc,
+            subscriber,
+            score,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Linkage {
+    pub linked: Vec<Link>,
+    pub review: Vec<Link>,
+    pub unmatched: Vec<Unmatched>,
+    pub warnings: Vec<Warning>,
+}
+
+// the first record in the candidates with an identical street wins, then
+// the first with a street that matches roughly; failing both, the closest
+// candidate is handed back for the exception report. Where the Fabric
+// carries several records for one street address (as it can for MDUs), a
+// record for the subscriber's own unit is preferred over the first one.
+fn best_street_match<'a>(
+    address: &NormalizedAddress,
+    candidates: impl Iterator<Item = (&'a FccRecord, NormalizedAddress)>,
+    config: &MatchConfig,
+) -> Result<(&'a FccRecord, MatchTier), Option<(u8, &'a FccRecord)>> {
+    let mut exact: Option<(&FccRecord, bool)> = None;
+    let mut fuzzy: Option<(&FccRecord, bool)> = None;
+    let mut closest: Option<(u8, &FccRecord)> = None;
+
+    let prefer =
+        |current: Option<(&'a FccRecord, bool)>, y: &'a FccRecord, same_unit: bool| match current {
+            Some((_, false)) if same_unit => Some((y, same_unit)),
+            None => Some((y, same_unit)),
+            current => current,
+        };
+
+    for (y, fcc_address) in candidates {
+        let same_unit = address.unit.is_some() && fcc_address.unit == address.unit;
+
+        if fcc_address.street == address.street {
+            if same_unit {
+                return Ok((y, MatchTier::Exact));
             }
 
-            linked
+            exact = prefer(exact, y, same_unit);
+            continue;
+        }
+
+        // check if the street names match roughly
+        let score = fuzz::ratio(&address.street, &fcc_address.street);
+
+        if score >= config.threshold {
+            fuzzy = prefer(fuzzy, y, same_unit);
+        } else if closest.is_none_or(|(best, _)| score > best) {
+            closest = Some((score, y));
+        }
+    }
+
+    exact
+        .map(|(y, _)| (y, MatchTier::Exact))
+        .or(fuzzy.map(|(y, _)| (y, MatchTier::Fuzzy)))
+        .ok_or(closest)
+}
+
+fn link_address(
+    index: &FccIndex,
+    x: &Subscriber,
+    config: &MatchConfig,
+) -> Result<(FccRecord, MatchTier), UnmatchedReason> {
+    let (Some(emerald_address), Some(emerald_city)) =
+        (x.normalized_service_address(), x.normalized_service_city())
+    else {
+        return Err(UnmatchedReason::MissingAddress);
+    };
+
+    if !index.contains_city(&emerald_city) {
+        return Err(UnmatchedReason::NoCityMatch);
+    }
+
+    // the index only hands back records where the city matches, all
+    // the digits match (to minimize false positives in the fuzzy
+    // matching) and the address numbers match exactly
+    let key = AddressKey::new(&emerald_address, &emerald_city);
+
+    match best_street_match(&emerald_address, index.candidates(&key), config) {
+        Ok((y, tier)) => {
+            log::debug!(
+                "MATCH ({tier}): {} => {:?}",
+                emerald_address,
+                y.address_primary
+            );
+            Ok((y.clone(), tier))
+        }
+        Err(Some((score, candidate))) => Err(UnmatchedReason::BelowThreshold {
+            score,
+            candidate: Box::new(candidate.clone()),
+        }),
+        Err(None) => Err(UnmatchedReason::DigitMismatch),
+    }
+}
+
+// billing often carries the mailing city rather than the one the Fabric
+// uses, so within the customer's own census block the city is ignored
+// and only the house number, digits and street have to line up
+fn link_census_block(index: &FccIndex, x: &Subscriber, config: &MatchConfig) -> Option<FccRecord> {
+    let block = x.census_block.as_deref()?;
+    let emerald_address = x.normalized_service_address()?;
+
+    let candidates = index.block(block).filter(|(_, fcc_address)| {
+        fcc_address.number == emerald_address.number
+            && fcc_address.digits() == emerald_address.digits()
+    });
+
+    best_street_match(&emerald_address, candidates, config)
+        .ok()
+        .map(|(y, _)| {
+            log::debug!(
+                "MATCH (census_block): {} => {:?}",
+                emerald_address,
+                y.address_primary
+            );
+            y.clone()
+        })
+}
+
+// by the subscriber's coordinates when the source has them, otherwise by
+// the geocoded service address, so only when GEOCODER_URL is set
+fn link_nearest(index: &FccIndex, x: &Subscriber) -> Option<FccRecord> {
+    let coordinates = match x.coordinates() {
+        Some(coordinates) => coordinates,
+        None => GEOCODER.as_ref()?.geocode(&x.one_line_service_address()?)?,
+    };
+
+    index
+        .nearest(coordinates, NEAREST_METERS)
+        .map(|(y, distance)| {
+            log::debug!(
+                "MATCH (nearest): {coordinates:?} => {} at {distance:.0}m",
+                y.location_id
+            );
+            y.clone()
+        })
+}
+
+fn link_subscription(
+    index: &FccIndex,
+    x: &Subscriber,
+    config: &MatchConfig,
+) -> Result<(FccRecord, MatchTier), UnmatchedReason> {
+    link_address(index, x, config).or_else(|reason| {
+        // a near miss on the address goes to review rather than letting a
+        // coarser tier link it somewhere else
+        if let UnmatchedReason::BelowThreshold { score, .. } = reason {
+            if score >= config.review_threshold {
+                return Err(reason);
+            }
+        }
+
+        link_census_block(index, x, config)
+            .map(|y| (y, MatchTier::CensusBlock))
+            .or_else(|| link_nearest(index, x).map(|y| (y, MatchTier::Nearest)))
+            // when every tier fails, the address tier's reason is the one
+            // staff can act on
+            .ok_or(reason)
+    })
+}
+
+enum Outcome {
+    Linked(Link),
+    Review(Link),
+    Unmatched(Unmatched),
+}
+
+fn link_one(
+    index: &FccIndex,
+    x: Subscriber,
+    overrides: &Overrides,
+    prior: &PriorLinks,
+    config: &MatchConfig,
+) -> (Outcome, Option<Warning>) {
+    let mut warning: Option<Warning> = None;
+
+    // a manual override skips the matcher entirely, unless the location
+    // it points to isn't in this Fabric
+    if let Some(entry) = overrides.lookup(&x) {
+        if let Some(y) = index.location(&entry.location_id) {
+            return (
+                Outcome::Linked(Link::new(y.clone(), x, MatchTier::Override)),
+                None,
+            );
+        }
+
+        let message = format!(
+            "override location {} is not in the Fabric",
+            entry.location_id
+        );
+        log::warn!("{message}");
+        warning = Some(Warning {
+            subscription_id: x.subscription_id.clone(),
+            customer_id: x.customer_id.clone(),
+            message,
+        });
+    }
+
+    // a subscriber linked by an earlier run keeps its location while it's
+    // still valid, so filings stay consistent from one period to the next
+    if let Some(y) = prior.lookup(index, &x, config) {
+        return (
+            Outcome::Linked(Link::new(y.clone(), x, MatchTier::Carryover)),
+            warning,
+        );
+    }
+
+    let outcome = match link_subscription(index, &x, config) {
+        Ok((y, tier)) => Outcome::Linked(Link::new(y, x, tier)),
+        Err(UnmatchedReason::BelowThreshold { score, candidate })
+            if score >= config.review_threshold =>
+        {
+            Outcome::Review(Link::new(*candidate, x, MatchTier::Fuzzy))
+        }
+        Err(reason) => Outcome::Unmatched(Unmatched {
+            subscriber: x,
+            reason,
+        }),
+    };
+
+    (outcome, warning)
+}
+
+// Subscriptions are matched in parallel, but the results are gathered
+// back in their original order so a run is reproducible no matter how
+// many threads did the work.
+pub fn link(
+    index: &FccIndex,
+    subscribers: Vec<Subscriber>,
+    overrides: &Overrides,
+    prior: &PriorLinks,
+    config: &MatchConfig,
+) -> Linkage {
+    let outcomes: Vec<(Outcome, Option<Warning>)> = THREAD_POOL.install(|| {
+        subscribers
+            .into_par_iter()
+            .map(|x| link_one(index, x, overrides, prior, config))
+            .collect()
+    });
+
+    let mut linkage = Linkage::default();
+
+    for (outcome, warning) in outcomes {
+        match outcome {
+            Outcome::Linked(link) => linkage.linked.push(link),
+            Outcome::Review(link) => linkage.review.push(link),
+            Outcome::Unmatched(unmatched) => linkage.unmatched.push(unmatched),
+        }
+
+        linkage.warnings.extend(warning);
+    }
+
+    linkage
+}
+
+// Subscribers linked to one BSL, for comparing against the Fabric's
+// unit_count. More subscribers than units is almost always a data error
+// on one side or the other that has to be fixed before filing.
+#[derive(Debug, Clone)]
+pub struct UnitOccupancy {
+    pub fcc: FccRecord,
+    pub subscribers: usize,
+    pub units: Vec<String>,
+}
+
+impl UnitOccupancy {
+    pub fn exceeds_unit_count(&self) -> bool {
+        self.subscribers as u64 > self.fcc.unit_count
+    }
+}
+
+pub fn summarize_units(linked_records: &[Link]) -> Vec<UnitOccupancy> {
+    let mut locations: HashMap<&str, (&FccRecord, HashSet<String>, HashSet<String>)> =
+        HashMap::new();
+
+    for (position, link) in linked_records.iter().enumerate() {
+        let (_, subscribers, units) = locations
+            .entry(&link.fcc.location_id)
+            .or_insert_with(|| (&link.fcc, HashSet::new(), HashSet::new()));
+
+        // without any ids the service address stands in for the customer,
+        // and without that each link counts on its own
+        let subscriber = link
+            .subscriber
+            .subscriber_id()
+            .cloned()
+            .or_else(|| {
+                link.subscriber
+                    .normalized_service_address()
+                    .map(|address| format!("address {address}"))
+            })
+            .unwrap_or_else(|| format!("link {position}"));
+        subscribers.insert(subscriber);
+
+        if let Some(unit) = link
+            .subscriber
+            .normalized_service_address()
+            .and_then(|address| address.unit)
+        {
+            units.insert(unit.to_string());
+        }
+    }
+
+    let mut occupancy: Vec<UnitOccupancy> = locations
+        .into_values()
+        .map(|(fcc, subscribers, units)| {
+            let mut units: Vec<String> = units.into_iter().collect();
+            units.sort();
+
+            UnitOccupancy {
+                fcc: fcc.clone(),
+                subscribers: subscribers.len(),
+                units,
+            }
         })
-        .collect()
+        .collect();
+    occupancy.sort_by(|a, b| a.fcc.location_id.cmp(&b.fcc.location_id));
+
+    occupancy
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
@@ -86,12 +519,6 @@ pub struct Summation {
     pub residential: u16,
 }
 
-fn is_consumer(text: String) -> bool {
-    text.to_uppercase() == "RESIDENTIAL"
-        || text.to_uppercase() == "RESIDENTAIL"
-        || text.to_uppercase() == "RRESIDENTIAL"
-}
-
 // I'm using and mutating the weird "111,222,333,444,555" string from
 // the FCC report to classify the tracts. I don't know why they don't
 // just include an eleven character version since that's what they want
@@ -103,85 +530,318 @@ fn get_tract(text: String) -> String {
     pruned
 }
 
+// Explicit technology and speeds (from an import that doesn't use our
+// plans) describe the service directly; otherwise it's the plan's.
+fn product_type(subscriber: &Subscriber, catalog: &ProductCatalog) -> Option<ProductType> {
+    if let (Some(technology_code), Some(download), Some(upload)) = (
+        subscriber.technology_code,
+        subscriber.download_speed,
+        subscriber.upload_speed,
+    ) {
+        let medium = ServiceMedium::from_technology_code(technology_code)?;
+
+        return Some(ProductType::Internet(
+            (medium, technology_code, upload, download, upload, download).into(),
+        ));
+    }
+
+    catalog.product_type(subscriber.plan_id.as_ref()?)
+}
+
+fn tally_tract(
+    summarization: &mut HashMap<TractSummationKey, Summation>,
+    link: Link,
+    catalog: &ProductCatalog,
+) {
+    let Link {
+        fcc, subscriber, ..
+    } = link;
+
+    if let (Some(product_type), Some(class)) =
+        (product_type(&subscriber, catalog), subscriber.class)
+    {
+        let key = TractSummationKey {
+            tract_id: get_tract(fcc.block_geoid),
+            product_type,
+        };
+
+        if !summarization.contains_key(&key) {
+            summarization.insert(
+                key,
+                Summation {
+                    total: 1,
+                    residential: if class == CustomerClass::Residential {
+                        1
+                    } else {
+                        0
+                    },
+                },
+            );
+        } else if let Some(existing) = summarization.get(&key) {
+            summarization.insert(
+                key,
+                Summation {
+                    total: existing.total + 1,
+                    residential: if class == CustomerClass::Residential {
+                        existing.residential + 1
+                    } else {
+                        existing.residential
+                    },
+                },
+            );
+        }
+    }
+}
+
+fn merge_tracts(
+    mut summarization: HashMap<TractSummationKey, Summation>,
+    other: HashMap<TractSummationKey, Summation>,
+) -> HashMap<TractSummationKey, Summation> {
+    for (key, summation) in other {
+        let existing = summarization.entry(key).or_insert(Summation {
+            total: 0,
+            residential: 0,
+        });
+        existing.total += summation.total;
+        existing.residential += summation.residential;
+    }
+
+    summarization
+}
+
 pub fn summarize_tracts(
-    linked_records: Vec<(FccRecord, SubscriptionApiItem)>,
+    linked_records: Vec<Link>,
+    catalog: &ProductCatalog,
 ) -> HashMap<TractSummationKey, Summation> {
-    let mut summarization: HashMap<TractSummationKey, Summation> = HashMap::new();
-
-    for (fcc, emerald) in linked_records {
-        if let Some(plan_id) = emerald.subscription.plan_id {
-            let plan_id = plan_id.replace("eugspfld", "").replace("-12", "");
-            if let (Some(product_type), Some(cf_residentialbusiness)) = (
-                (*PRODUCT_CODES).get(&plan_id),
-                emerald.customer.cf_residentialbusiness,
-            ) {
-                let key = TractSummationKey {
-                    tract_id: get_tract(fcc.block_geoid),
-                    product_type: product_type.clone(),
-                };
-
-                if !summarization.contains_key(&key) {
-                    summarization.insert(
-                        key,
-                        Summation {
-                            total: 1,
-                            residential: if is_consumer(cf_residentialbusiness) {
-                                1
-                            } else {
-                                0
-                            },
-                        },
-                    );
-                } else if let Some(existing) = summarization.get(&key) {
-                    summarization.insert(
-                        key,
-                        Summation {
-                            total: existing.total + 1,
-                            residential: if is_consumer(cf_residentialbusiness) {
-                                existing.residential + 1
-                            } else {
-                                existing.residential
-                            },
-                        },
-                    );
+    THREAD_POOL.install(|| {
+        linked_records
+            .into_par_iter()
+            .fold(HashMap::new, |mut summarization, link| {
+                tally_tract(&mut summarization, link, catalog);
+                summarization
+            })
+            .reduce(HashMap::new, merge_tracts)
+    })
+}
+
+fn tally_location(
+    summarization: &mut HashSet<LocationSummationKey>,
+    link: Link,
+    catalog: &ProductCatalog,
+) {
+    let Link {
+        fcc, subscriber, ..
+    } = link;
+
+    if let Some(ProductType::Internet(service_profile)) = product_type(&subscriber, catalog) {
+        let key = LocationSummationKey {
+            location_id: fcc.location_id,
+            product_type: ProductType::Internet(service_profile.equalize_committed()),
+        };
+
+        if !summarization.contains(&key) {
+            summarization.insert(key);
+        } else if let Some(existing) = summarization.get(&key) {
+            if let ProductType::Internet(existing_service_profile) = existing.product_type.clone() {
+                if existing_service_profile.available_bandwidth_down
+                    < service_profile.available_bandwidth_down
+                {
+                    summarization.insert(key);
                 }
             }
         }
     }
-
-    summarization
 }
 
 pub fn summarize_locations(
-    linked_records: Vec<(FccRecord, SubscriptionApiItem)>,
+    linked_records: Vec<Link>,
+    catalog: &ProductCatalog,
 ) -> HashSet<LocationSummationKey> {
-    let mut summarization: HashSet<LocationSummationKey> = HashSet::new();
-
-    for (fcc, emerald) in linked_records {
-        if let Some(plan_id) = emerald.subscription.plan_id {
-            let plan_id = plan_id.replace("eugspfld", "").replace("-12", "");
-            if let Some(ProductType::Internet(service_profile)) = (*PRODUCT_CODES).get(&plan_id) {
-                let key = LocationSummationKey {
-                    location_id: fcc.location_id,
-                    product_type: ProductType::Internet(service_profile.equalize_committed()),
-                };
-
-                if !summarization.contains(&key) {
-                    summarization.insert(key);
-                } else if let Some(existing) = summarization.get(&key) {
-                    if let ProductType::Internet(existing_service_profile) =
-                        existing.product_type.clone()
-                    {
-                        if existing_service_profile.available_bandwidth_down
-                            < service_profile.available_bandwidth_down
-                        {
-                            summarization.insert(key);
-                        }
-                    }
-                }
-            }
+    THREAD_POOL.install(|| {
+        linked_records
+            .into_par_iter()
+            .fold(HashSet::new, |mut summarization, link| {
+                tally_location(&mut summarization, link, catalog);
+                summarization
+            })
+            .reduce(HashSet::new, |mut summarization, other| {
+                summarization.extend(other);
+                summarization
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(location_id: &str, address: &str, city: &str) -> FccRecord {
+        FccRecord {
+            location_id: location_id.to_string(),
+            address_primary: Some(address.to_string()),
+            city: Some(city.into()),
+            state: "OR".into(),
+            zip: None,
+            zip_suffix: None,
+            unit_count: 1,
+            bsl_flag: true,
+            building_type_code: 'R',
+            land_use_code: 1,
+            address_confidence_code: 1,
+            county_geoid: "41039".into(),
+            block_geoid: "410390001001000".to_string(),
+            h3_9: "8928".into(),
+            latitude: 44.05,
+            longitude: -123.09,
         }
     }
 
-    summarization
+    fn subscriber(address: &str, city_st_zip: &str) -> Subscriber {
+        Subscriber {
+            service_address: Some(address.to_string()),
+            service_city_st_zip: Some(city_st_zip.to_string()),
+            ..Subscriber::default()
+        }
+    }
+
+    // a linear scan under the same rules, to check the index against
+    fn scan(fcc: &[FccRecord], x: &Subscriber) -> Option<String> {
+        let emerald_address = x.normalized_service_address()?;
+        let emerald_city = x.normalized_service_city()?;
+
+        let candidates: Vec<(&FccRecord, NormalizedAddress)> = fcc
+            .iter()
+            .filter_map(|y| {
+                let fcc_address = y.normalized_address()?;
+                let matches = y.normalized_city()? == emerald_city
+                    && emerald_address.digits() == fcc_address.digits()
+                    && emerald_address.number == fcc_address.number
+                    && fuzz::ratio(&emerald_address.street, &fcc_address.street) > 80;
+
+                matches.then_some((y, fcc_address))
+            })
+            .collect();
+
+        // the subscriber's own unit wins over the first record
+        candidates
+            .iter()
+            .find(|(_, fcc_address)| {
+                emerald_address.unit.is_some() && fcc_address.unit == emerald_address.unit
+            })
+            .or(candidates.first())
+            .map(|(y, _)| y.location_id.clone())
+    }
+
+    #[test]
+    fn links_the_same_records_as_a_linear_scan() {
+        let fabric = vec![
+            record("1", "123 MAIN ST", "EUGENE"),
+            record("2", "123 MAIN ST", "EUGENE"),
+            record("3", "123 MAIN ST", "SPRINGFIELD"),
+            record("4", "45 W 11TH AVE", "EUGENE"),
+            record("5", "45 W 11TH AVE APT 2", "EUGENE"),
+            record("6", "9 OAK STREET", "EUGENE"),
+        ];
+        let subscribers = vec![
+            subscriber("123 Main St", "Eugene, OR 97401"),
+            subscriber("123 Main St", "Springfield, OR 97477"),
+            subscriber("123 Main Street", "Eugene, OR 97401"),
+            subscriber("45 W 11th Ave Apt 2", "Eugene, OR 97401"),
+            subscriber("45 W 11th Av", "Eugene, OR 97401"),
+            subscriber("9 Oak St", "Eugene, OR 97401"),
+            subscriber("77 Main St", "Eugene, OR 97401"),
+            subscriber("123 Main St", "Eugene OR 97401"),
+        ];
+
+        let expected: Vec<(String, String)> = subscribers
+            .iter()
+            .filter_map(|x| {
+                let location_id = scan(&fabric, x)?;
+                Some((location_id, x.service_address.clone()?))
+            })
+            .collect();
+
+        let linked: Vec<(String, String)> = link(
+            &FccIndex::new(fabric),
+            subscribers,
+            &Overrides::default(),
+            &PriorLinks::default(),
+            &MatchConfig::default(),
+        )
+        .linked
+        .into_iter()
+        .map(|link| {
+            (
+                link.fcc.location_id,
+                link.subscriber.service_address.unwrap(),
+            )
+        })
+        .collect();
+
+        assert_eq!(linked, expected);
+        assert_eq!(
+            linked
+                .iter()
+                .map(|(location_id, _)| location_id.as_str())
+                .collect::<Vec<&str>>(),
+            // unlike the raw comparison, suffixes are abbreviated before the
+            // streets are compared and units are left out of the digits, so
+            // the unit record has to be preferred explicitly
+            ["1", "3", "1", "5", "4", "6"]
+        );
+    }
+
+    #[test]
+    fn holds_near_misses_for_review_before_the_coarser_tiers() {
+        let fabric = vec![
+            record("1", "12 MAPLE ST", "EUGENE"),
+            FccRecord {
+                block_geoid: "410390002002000".to_string(),
+                ..record("2", "12 MAPLE DR", "SPRINGFIELD")
+            },
+        ];
+
+        // "MAPLE DR" against "MAPLE ST" scores 75, and the subscriber's
+        // block holds a single BSL that would otherwise link
+        let mut x = subscriber("12 Maple Dr", "Eugene, OR 97401");
+        x.census_block = Some("410390002002000".to_string());
+
+        let linkage = link(
+            &FccIndex::new(fabric),
+            vec![x],
+            &Overrides::default(),
+            &PriorLinks::default(),
+            &MatchConfig::default(),
+        );
+
+        assert!(linkage.linked.is_empty());
+        assert_eq!(
+            linkage
+                .review
+                .iter()
+                .map(|link| link.fcc.location_id.as_str())
+                .collect::<Vec<&str>>(),
+            ["1"]
+        );
+    }
+
+    #[test]
+    fn counts_subscribers_without_ids_by_address() {
+        let fcc = record("1", "9 OAK ST", "EUGENE");
+        let linked: Vec<Link> = ["9 Oak St Apt 1", "9 Oak St Apt 2", "9 Oak St Apt 2"]
+            .into_iter()
+            .map(|address| {
+                Link::new(
+                    fcc.clone(),
+                    subscriber(address, "Eugene, OR 97401"),
+                    MatchTier::Fuzzy,
+                )
+            })
+            .collect();
+
+        let occupancy = summarize_units(&linked);
+        assert_eq!(occupancy.len(), 1);
+        assert_eq!(occupancy[0].subscribers, 2);
+        assert_eq!(occupancy[0].units, ["APT 1", "APT 2"]);
+    }
 }
@@ -0,0 +1,293 @@
+#[macro_use] extern crate rocket;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{env, fs, process};
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::State;
+use serde_json::{json, Value};
+
+const USAGE: &str = "usage: fake_chargebee [--fixtures DIR] [--port PORT] [--throttle N] [--fail N] [--unauthorized]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    process::exit(2);
+}
+
+// Every subscription in the fixtures, in file then list order, and which
+// requests to answer with an error instead.
+struct Fixtures {
+    subscriptions: Vec<Value>,
+    // every Nth request is rate limited (429) or fails (500)
+    throttle: Option<u64>,
+    fail: Option<u64>,
+    unauthorized: bool,
+    requests: AtomicU64,
+}
+
+// A fixture file is either a JSON array of list entries or a whole
+// ApiResponse, e.g. one saved from the real API.
+fn load(directory: &str) -> Vec<Value> {
+    let mut paths: Vec<_> = glob::glob(&format!("{directory}/*.json"))
+        .expect("Failed to read glob pattern")
+        .filter_map(Result::ok)
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        fail(&format!("no fixtures in {directory}"));
+    }
+
+    paths
+        .into_iter()
+        .flat_map(|path| {
+            let text = fs::read_to_string(&path)
+                .unwrap_or_else(|e| fail(&format!("unable to read {}: {e}", path.display())));
+            let fixture: Value = serde_json::from_str(&text)
+                .unwrap_or_else(|e| fail(&format!("unable to parse {}: {e}", path.display())));
+
+            match fixture {
+                Value::Array(list) => list,
+                Value::Object(mut response) => match response.remove("list") {
+                    Some(Value::Array(list)) => list,
+                    _ => fail(&format!("{} has no list", path.display())),
+                },
+                _ => fail(&format!("{} isn't a list of subscriptions", path.display())),
+            }
+        })
+        .collect()
+}
+
+// the raw query, since ChargeBee's filter names (status[is]) aren't
+// something a Rocket form will take
+struct Filters(Vec<(String, String)>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Filters {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let filters = request
+            .uri()
+            .query()
+            .map(|query| {
+                query
+                    .segments()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        request::Outcome::Success(Filters(filters))
+    }
+}
+
+impl Filters {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Authorized(bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(Authorized(
+            request.headers().get_one("Authorization").is_some(),
+        ))
+    }
+}
+
+struct Reply {
+    status: Status,
+    body: Value,
+    retry_after: Option<u64>,
+}
+
+impl Reply {
+    // shaped like ChargeBee's own error responses
+    fn error(status: Status, code: &str, message: &str) -> Self {
+        Reply {
+            status,
+            body: json!({
+                "message": message,
+                "type": "invalid_request",
+                "api_error_code": code,
+                "http_status_code": status.code,
+            }),
+            retry_after: None,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Reply {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Json(self.body).respond_to(request)?;
+        response.set_status(self.status);
+
+        if let Some(seconds) = self.retry_after {
+            response.set_raw_header("Retry-After", seconds.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+fn matches(subscription: &Value, filters: &Filters) -> bool {
+    let status = subscription["subscription"]["status"].as_str();
+
+    if let Some(wanted) = filters.get("status[is]") {
+        return status == Some(wanted);
+    }
+
+    if let Some(wanted) = filters.get("status[in]") {
+        let wanted: Vec<String> = serde_json::from_str(wanted).unwrap_or_default();
+        return status.is_some_and(|status| wanted.iter().any(|wanted| wanted == status));
+    }
+
+    true
+}
+
+// Pages through the fixtures like GET /api/v2/subscriptions: limit (1 to
+// 100, 10 by default), offset from the previous page's next_offset, and
+// status[is] or status[in]. Other filters are ignored.
+#[get("/api/v2/subscriptions")]
+fn subscriptions(fixtures: &State<Fixtures>, filters: Filters, authorized: Authorized) -> Reply {
+    let request = fixtures.requests.fetch_add(1, Ordering::SeqCst) + 1;
+
+    if fixtures.unauthorized || !authorized.0 {
+        return Reply::error(
+            Status::Unauthorized,
+            "api_authentication_failed",
+            "Sorry, authentication failed. Invalid api key",
+        );
+    }
+
+    if fixtures.throttle.is_some_and(|n| request.is_multiple_of(n)) {
+        return Reply {
+            retry_after: Some(1),
+            ..Reply::error(
+                Status::TooManyRequests,
+                "api_request_limit_exceeded",
+                "Sorry, access has been blocked temporarily due to request count exceeding acceptable limits.",
+            )
+        };
+    }
+
+    if fixtures.fail.is_some_and(|n| request.is_multiple_of(n)) {
+        return Reply::error(
+            Status::InternalServerError,
+            "internal_error",
+            "Sorry, Something went wrong when trying to process the request.",
+        );
+    }
+
+    let limit: usize = match filters.get("limit").map(str::parse) {
+        None => 10,
+        Some(Ok(limit @ 1..=100)) => limit,
+        Some(_) => {
+            return Reply::error(
+                Status::BadRequest,
+                "param_out_of_range",
+                "limit : The value should be between 1 and 100",
+            )
+        }
+    };
+    let offset: usize = match filters.get("offset").map(str::parse) {
+        None => 0,
+        Some(Ok(offset)) => offset,
+        Some(Err(_)) => {
+            return Reply::error(
+                Status::BadRequest,
+                "param_wrong_value",
+                "offset : invalid value",
+            )
+        }
+    };
+
+    let matching: Vec<&Value> = fixtures
+        .subscriptions
+        .iter()
+        .filter(|subscription| matches(subscription, &filters))
+        .collect();
+    let page: Vec<&Value> = matching.iter().skip(offset).take(limit).cloned().collect();
+    let next_offset = (offset + limit < matching.len()).then(|| (offset + limit).to_string());
+
+    Reply {
+        status: Status::Ok,
+        body: json!({ "list": page, "next_offset": next_offset }),
+        retry_after: None,
+    }
+}
+
+// A stand-in for the ChargeBee subscriptions endpoint, so the service can
+// run without live credentials:
+//
+//     cargo run --bin fake_chargebee
+//     API_URL=http://localhost:8001/api/v2/subscriptions API_KEY=test: cargo run --bin server
+#[launch]
+fn rocket() -> _ {
+    env_logger::init();
+
+    let mut directory = "fixtures/chargebee".to_string();
+    let mut port: u16 = 8001;
+    let mut throttle = None;
+    let mut fail_every = None;
+    let mut unauthorized = false;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("{arg} needs a value")))
+        };
+        let mut every = || {
+            Some(
+                value()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .unwrap_or_else(|| fail(&format!("{arg} must be a positive number"))),
+            )
+        };
+
+        match arg.as_str() {
+            "--fixtures" => directory = value(),
+            "--port" => {
+                port = value()
+                    .parse()
+                    .unwrap_or_else(|_| fail("--port must be a number"))
+            }
+            "--throttle" => throttle = every(),
+            "--fail" => fail_every = every(),
+            "--unauthorized" => unauthorized = true,
+            _ => fail(&format!("unexpected argument {arg}")),
+        }
+    }
+
+    let fixtures = Fixtures {
+        subscriptions: load(&directory),
+        throttle,
+        fail: fail_every,
+        unauthorized,
+        requests: AtomicU64::new(0),
+    };
+    log::info!(
+        "serving {} subscriptions from {directory}",
+        fixtures.subscriptions.len()
+    );
+
+    rocket::custom(rocket::Config::figment().merge(("port", port)))
+        .manage(fixtures)
+        .mount("/", routes![subscriptions])
+}
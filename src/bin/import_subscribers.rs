@@ -0,0 +1,123 @@
+use std::{env, fs, process};
+
+use fcc_reporting::emerald::ChargeBeeSource;
+use fcc_reporting::manifest::RunManifest;
+use fcc_reporting::pipeline::{run, FabricSource};
+use fcc_reporting::snapshot::{Snapshot, SnapshotSource};
+use fcc_reporting::subscriber::{ColumnMapping, CsvSource, MergedSource, SubscriberSource};
+
+const USAGE: &str = "usage: import_subscribers --csv FILE (--release ID | --fabric FILE [--release-date YYYY-MM-DD]) [--mapping FILE.json] [--merge [--snapshot ID]] [--as-of YYYY-MM-DD]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}\n{USAGE}");
+    process::exit(2);
+}
+
+// Links a subscriber CSV against a Fabric release and writes the same
+// reports a /focus run does, without going through the web interface.
+fn main() {
+    env_logger::init();
+
+    let mut csv: Option<String> = None;
+    let mut release: Option<i64> = None;
+    let mut fabric: Option<String> = None;
+    let mut release_date: Option<String> = None;
+    let mut mapping = ColumnMapping::default();
+    let mut merge = false;
+    let mut as_of: Option<chrono::NaiveDate> = None;
+    let mut snapshot: Option<String> = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("{arg} needs a value")))
+        };
+
+        match arg.as_str() {
+            "--csv" => csv = Some(value()),
+            "--release" => {
+                release = Some(
+                    value()
+                        .parse()
+                        .unwrap_or_else(|_| fail("--release must be a number")),
+                )
+            }
+            "--fabric" => fabric = Some(value()),
+            "--release-date" => release_date = Some(value()),
+            "--mapping" => {
+                let path = value();
+                let text = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| fail(&format!("unable to read {path}: {e}")));
+                mapping = serde_json::from_str(&text)
+                    .unwrap_or_else(|e| fail(&format!("unable to parse {path}: {e}")));
+            }
+            "--merge" => merge = true,
+            "--as-of" => {
+                as_of = Some(
+                    chrono::NaiveDate::parse_from_str(&value(), "%Y-%m-%d")
+                        .unwrap_or_else(|_| fail("--as-of must be YYYY-MM-DD")),
+                )
+            }
+            "--snapshot" => snapshot = Some(value()),
+            _ => fail(&format!("unexpected argument {arg}")),
+        }
+    }
+
+    if let Err(e) = mapping.validate() {
+        fail(&e.to_string());
+    }
+
+    let path = csv.unwrap_or_else(|| fail("--csv is required"));
+    let source = match (fabric, release) {
+        (Some(filename), None) => FabricSource::Upload {
+            filename,
+            release_date,
+        },
+        (None, Some(release)) => FabricSource::Release(release),
+        _ => fail("give one of --release or --fabric"),
+    };
+
+    let csv = Box::new(CsvSource {
+        name: format!("csv:{path}"),
+        path,
+        mapping,
+    });
+    let subscribers: Box<dyn SubscriberSource> = match (merge, snapshot) {
+        (true, Some(id)) => {
+            as_of = Snapshot::as_of_for(&id, as_of).unwrap_or_else(|e| fail(&e.to_string()));
+
+            Box::new(MergedSource::new(vec![
+                Box::new(SnapshotSource { id }),
+                csv,
+            ]))
+        }
+        (true, None) => Box::new(MergedSource::new(vec![
+            Box::new(ChargeBeeSource::new(as_of)),
+            csv,
+        ])),
+        (false, Some(_)) => fail("--snapshot only applies with --merge"),
+        (false, None) => csv,
+    };
+
+    let uuid = uuid::Uuid::new_v4().to_string().replace('-', "");
+    run(uuid.clone(), source, subscribers, as_of);
+
+    let manifest: Option<RunManifest> =
+        fs::read_to_string(format!("output/reports/manifest-{uuid}.json"))
+            .ok()
+            .and_then(|manifest| serde_json::from_str(&manifest).ok());
+
+    match manifest {
+        Some(manifest) if manifest.error.is_none() => println!("{uuid}"),
+        Some(manifest) => {
+            eprintln!("{}", manifest.error.unwrap_or_default());
+            process::exit(1);
+        }
+        None => {
+            eprintln!("run {uuid} didn't write a manifest");
+            process::exit(1);
+        }
+    }
+}
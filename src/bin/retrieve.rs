@@ -1,6 +1,11 @@
 use fcc_reporting::emerald::retrieve_subscriptions;
 
 fn main() {
-    let subscriptions = retrieve_subscriptions();
-    println!("{subscriptions:#?}");
+    match retrieve_subscriptions() {
+        Ok(subscriptions) => println!("{subscriptions:#?}"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
 }
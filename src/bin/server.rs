@@ -2,7 +2,7 @@
 
 use std::path::Path;
 
-use fcc_reporting::routes::{focus::upload_focus_data, reports::{report_list, statistics_list}};
+use fcc_reporting::routes::{catalog::{product_catalog, set_catalog_plan, delete_catalog_plan}, fabric::{fabric_release_list, fabric_release, delete_fabric_release, diff_fabric_releases}, focus::{upload_focus_data, cancel_focus_run, RunningRetrievals}, overrides::{override_list, add_override, delete_override}, reports::{report_list, run_list, statistics_list}, snapshots::snapshot_list};
 use rocket::fs::{NamedFile, FileServer};
 
 #[get("/")]
@@ -17,12 +17,26 @@ fn rocket() -> _ {
     env_logger::init();
 
     rocket::build()
+        .manage(RunningRetrievals::default())
         .mount("/static", FileServer::from("static"))
         .mount("/report", FileServer::from("output/reports"))
         .mount("/", routes![
             index,
             upload_focus_data,
+            cancel_focus_run,
             report_list,
-            statistics_list
+            statistics_list,
+            run_list,
+            override_list,
+            add_override,
+            delete_override,
+            fabric_release_list,
+            fabric_release,
+            delete_fabric_release,
+            diff_fabric_releases,
+            snapshot_list,
+            product_catalog,
+            set_catalog_plan,
+            delete_catalog_plan
         ])
 }
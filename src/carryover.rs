@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use fuzzywuzzy::fuzz;
+
+use crate::analysis::{Link, MatchConfig, MatchTier};
+use crate::fcc::FccRecord;
+use crate::index::{digits, FccIndex};
+use crate::store::{FabricStore, StoreError};
+use crate::subscriber::Subscriber;
+
+// A subscriber's link from an earlier run, along with both addresses as
+// they were when it was made. location_id is stable across Fabric
+// releases, so the next run can reuse the link as long as neither side's
+// address has moved on since.
+#[derive(Debug, Clone)]
+pub struct PriorLink {
+    pub subscription_id: String,
+    pub customer_id: Option<String>,
+    pub location_id: String,
+    pub service_address: String,
+    pub fabric_address: String,
+}
+
+pub fn service_address(subscriber: &Subscriber) -> Option<String> {
+    let address = subscriber.normalized_service_address()?;
+    let city = subscriber.normalized_service_city().unwrap_or_default();
+
+    Some(format!("{address}, {city}"))
+}
+
+pub fn fabric_address(record: &FccRecord) -> String {
+    let street = record
+        .normalized_address()
+        .map(|address| address.to_string())
+        .or_else(|| record.address_primary.clone())
+        .unwrap_or_default();
+    let city = record.normalized_city().unwrap_or_default();
+
+    format!("{street}, {city}")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PriorLinks {
+    links: HashMap<String, PriorLink>,
+}
+
+impl PriorLinks {
+    // subscription ids are only unique within the billing system they
+    // came from, so links are remembered per source
+    pub fn load(store: &FabricStore, source: &str) -> Result<Self, StoreError> {
+        Ok(PriorLinks {
+            links: store
+                .subscriber_links(source)?
+                .into_iter()
+                .map(|link| (link.subscription_id.clone(), link))
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    // The remembered location, if it's still in this Fabric and still the
+    // same place. The subscriber's normalized address has to be unchanged;
+    // the Fabric's may have been touched up between releases but must keep
+    // its numbers and stay within the match threshold of what it was.
+    pub fn lookup<'a>(
+        &self,
+        index: &'a FccIndex,
+        subscriber: &Subscriber,
+        config: &MatchConfig,
+    ) -> Option<&'a FccRecord> {
+        let prior = self.links.get(subscriber.subscription_id.as_ref()?)?;
+
+        if service_address(subscriber)? != prior.service_address {
+            return None;
+        }
+
+        let record = index.location(&prior.location_id)?;
+        let current = fabric_address(record);
+
+        if digits(&current) != digits(&prior.fabric_address)
+            || fuzz::ratio(&current, &prior.fabric_address) < config.threshold
+        {
+            return None;
+        }
+
+        Some(record)
+    }
+
+    // Address links made by the matcher this run replace what was
+    // remembered. Carried over links keep the addresses they were first
+    // made with so small changes can't add up across releases, overrides
+    // are left out so removing one takes effect, and census block and
+    // nearest links are left out so they're matched afresh each run rather
+    // than outranking the address tiers from then on.
+    pub fn save(
+        store: &mut FabricStore,
+        source: &str,
+        release_id: i64,
+        links: &[Link],
+    ) -> Result<(), StoreError> {
+        let links: Vec<PriorLink> = links
+            .iter()
+            .filter(|link| matches!(link.score.tier, MatchTier::Exact | MatchTier::Fuzzy))
+            .filter_map(|link| {
+                Some(PriorLink {
+                    subscription_id: link.subscriber.subscription_id.clone()?,
+                    customer_id: link.subscriber.customer_id.clone(),
+                    location_id: link.fcc.location_id.clone(),
+                    service_address: service_address(&link.subscriber)?,
+                    fabric_address: fabric_address(&link.fcc),
+                })
+            })
+            .collect();
+
+        store.save_subscriber_links(source, release_id, &links)
+    }
+}
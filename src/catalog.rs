@@ -0,0 +1,482 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::emerald::{ProductType, ServiceMedium};
+use crate::subscriber::Subscriber;
+
+lazy_static! {
+    // like the overrides, edits are a read-modify-write of the whole file
+    static ref CATALOG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// the BDC technology codes the FCC accepts
+const TECHNOLOGY_CODES: [u8; 9] = [0, 10, 40, 50, 60, 61, 70, 71, 72];
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Write(toml::ser::Error),
+    // every problem found, one per line
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "unable to read the product catalog: {e}"),
+            CatalogError::Parse(e) => write!(f, "unable to parse the product catalog: {e}"),
+            CatalogError::Write(e) => write!(f, "unable to write the product catalog: {e}"),
+            CatalogError::Invalid(problems) => {
+                write!(f, "invalid product catalog: {}", problems.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CatalogError::Io(e) => Some(e),
+            CatalogError::Parse(e) => Some(e),
+            CatalogError::Write(e) => Some(e),
+            CatalogError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CatalogError {
+    fn from(e: io::Error) -> Self {
+        CatalogError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for CatalogError {
+    fn from(e: toml::de::Error) -> Self {
+        CatalogError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for CatalogError {
+    fn from(e: toml::ser::Error) -> Self {
+        CatalogError::Write(e)
+    }
+}
+
+// What a plan is. Speeds are in Mbps.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Plan {
+    Internet {
+        technology_code: u8,
+        committed_download: u16,
+        committed_upload: u16,
+        available_download: u16,
+        available_upload: u16,
+    },
+    Voip,
+    Fax,
+    Admin,
+}
+
+impl Plan {
+    fn problems(&self, plan_id: &str) -> Vec<String> {
+        let Plan::Internet {
+            technology_code,
+            committed_download,
+            committed_upload,
+            available_download,
+            available_upload,
+        } = *self
+        else {
+            return vec![];
+        };
+
+        let mut problems = vec![];
+
+        if !TECHNOLOGY_CODES.contains(&technology_code) {
+            problems.push(format!(
+                "{plan_id}: {technology_code} isn't a BDC technology code"
+            ));
+        } else if ServiceMedium::from_technology_code(technology_code).is_none() {
+            problems.push(format!(
+                "{plan_id}: technology code {technology_code} isn't one we deliver over"
+            ));
+        }
+
+        for (name, speed) in [
+            ("committed_download", committed_download),
+            ("committed_upload", committed_upload),
+            ("available_download", available_download),
+            ("available_upload", available_upload),
+        ] {
+            if speed == 0 {
+                problems.push(format!("{plan_id}: {name} must be positive"));
+            }
+        }
+
+        problems
+    }
+
+    fn product_type(&self) -> Option<ProductType> {
+        match *self {
+            Plan::Internet {
+                technology_code,
+                committed_download,
+                committed_upload,
+                available_download,
+                available_upload,
+            } => Some(ProductType::Internet(
+                (
+                    ServiceMedium::from_technology_code(technology_code)?,
+                    technology_code,
+                    committed_upload,
+                    committed_download,
+                    available_upload,
+                    available_download,
+                )
+                    .into(),
+            )),
+            Plan::Voip => Some(ProductType::Voip),
+            Plan::Fax => Some(ProductType::Fax),
+            Plan::Admin => Some(ProductType::Admin),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rewrite {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+// How billing's plan_ids are turned into catalog plan ids: the first
+// matching prefix and the first matching suffix are stripped (market codes
+// like eugspfld, term lengths like -12), then each rewrite is applied in
+// order, then the result is looked up in aliases for legacy plan names.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PlanIdRules {
+    pub prefixes: Vec<String>,
+    pub suffixes: Vec<String>,
+    pub rewrites: Vec<Rewrite>,
+    pub aliases: BTreeMap<String, String>,
+    #[serde(skip)]
+    compiled: Vec<Regex>,
+}
+
+impl PlanIdRules {
+    fn compile(&mut self) -> Vec<String> {
+        let mut problems = vec![];
+
+        self.compiled = self
+            .rewrites
+            .iter()
+            .filter_map(|rewrite| match Regex::new(&rewrite.pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    problems.push(format!("rewrite {:?}: {e}", rewrite.pattern));
+                    None
+                }
+            })
+            .collect();
+
+        problems
+    }
+
+    pub fn normalize(&self, plan_id: &str) -> String {
+        let mut plan_id = plan_id.trim();
+
+        if let Some(rest) = self
+            .prefixes
+            .iter()
+            .find_map(|prefix| plan_id.strip_prefix(prefix.as_str()))
+        {
+            plan_id = rest;
+        }
+
+        if let Some(rest) = self
+            .suffixes
+            .iter()
+            .find_map(|suffix| plan_id.strip_suffix(suffix.as_str()))
+        {
+            plan_id = rest;
+        }
+
+        let plan_id = self.compiled.iter().zip(&self.rewrites).fold(
+            plan_id.to_string(),
+            |plan_id, (regex, rewrite)| {
+                regex
+                    .replace_all(&plan_id, rewrite.replacement.as_str())
+                    .into_owned()
+            },
+        );
+
+        self.aliases.get(&plan_id).cloned().unwrap_or(plan_id)
+    }
+}
+
+// A plan_id billing used that nothing in the catalog matches.
+#[derive(Debug, Clone)]
+pub struct UnmappedPlan {
+    pub plan_id: String,
+    pub normalized: String,
+    pub subscriptions: usize,
+}
+
+// The plan_id -> ProductType mapping the reports are built from, read from
+// CATALOG_PATH (catalog.toml by default) so new plans don't need a release.
+// The version is a hash of the plans and plan_id rules, recorded in each
+// run's manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProductCatalog {
+    #[serde(default)]
+    plan_ids: PlanIdRules,
+    plans: BTreeMap<String, Plan>,
+    #[serde(skip)]
+    version: String,
+}
+
+impl ProductCatalog {
+    pub fn load() -> Result<Self, CatalogError> {
+        Self::parse(&fs::read_to_string(&*crate::CATALOG_PATH)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CatalogError> {
+        let mut catalog: ProductCatalog = toml::from_str(text)?;
+        catalog.validate()?;
+        catalog.version = catalog.hash()?;
+
+        Ok(catalog)
+    }
+
+    // also compiles the rewrites, so it has to run before lookups
+    pub fn validate(&mut self) -> Result<(), CatalogError> {
+        let mut problems = self.plan_ids.compile();
+
+        problems.extend(
+            self.plans
+                .iter()
+                .flat_map(|(plan_id, plan)| plan.problems(plan_id)),
+        );
+
+        for (alias, plan_id) in &self.plan_ids.aliases {
+            if !self.plans.contains_key(plan_id) {
+                problems.push(format!("alias {alias}: there's no plan {plan_id}"));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(CatalogError::Invalid(problems))
+        }
+    }
+
+    // of what's in the catalog rather than the file, so comments and
+    // ordering don't change it
+    fn hash(&self) -> Result<String, CatalogError> {
+        let digest = Sha256::digest(toml::to_string(self)?.as_bytes());
+
+        Ok(hex::encode(&digest[..6]))
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn plans(&self) -> &BTreeMap<String, Plan> {
+        &self.plans
+    }
+
+    pub fn plan_id_rules(&self) -> &PlanIdRules {
+        &self.plan_ids
+    }
+
+    // a plan_id that's in the catalog as is doesn't go through the rules
+    fn plan(&self, plan_id: &str) -> Option<&Plan> {
+        self.plans
+            .get(plan_id)
+            .or_else(|| self.plans.get(&self.plan_ids.normalize(plan_id)))
+    }
+
+    pub fn product_type(&self, plan_id: &str) -> Option<ProductType> {
+        self.plan(plan_id)?.product_type()
+    }
+
+    // The plan_ids of subscribers that depend on the catalog (no explicit
+    // technology code and speeds) but aren't in it, most used first.
+    pub fn unmapped_plans(&self, subscribers: &[Subscriber]) -> Vec<UnmappedPlan> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+
+        for subscriber in subscribers {
+            let explicit = subscriber.technology_code.is_some()
+                && subscriber.download_speed.is_some()
+                && subscriber.upload_speed.is_some();
+
+            match subscriber.plan_id.as_deref() {
+                Some(plan_id) if !explicit && self.plan(plan_id).is_none() => {
+                    *counts.entry(plan_id).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+
+        let mut unmapped: Vec<UnmappedPlan> = counts
+            .into_iter()
+            .map(|(plan_id, subscriptions)| UnmappedPlan {
+                plan_id: plan_id.to_string(),
+                normalized: self.plan_ids.normalize(plan_id),
+                subscriptions,
+            })
+            .collect();
+        unmapped.sort_by_key(|plan| std::cmp::Reverse(plan.subscriptions));
+
+        unmapped
+    }
+
+    fn save(&self) -> Result<(), CatalogError> {
+        let temporary = format!("{}.tmp", *crate::CATALOG_PATH);
+        fs::write(&temporary, toml::to_string_pretty(self)?)?;
+        fs::rename(temporary, &*crate::CATALOG_PATH)?;
+
+        Ok(())
+    }
+
+    // Copies this version to output/catalogs/<version>.toml, if it isn't
+    // there already, so the catalog a run recorded can be looked up later.
+    pub fn archive(&self) -> Result<(), CatalogError> {
+        let path = format!("output/catalogs/{}.toml", self.version);
+
+        if fs::metadata(&path).is_err() {
+            fs::create_dir_all("output/catalogs")?;
+            fs::write(path, toml::to_string_pretty(self)?)?;
+        }
+
+        Ok(())
+    }
+
+    // adding a plan that's already there replaces it; nothing is written
+    // unless the plan is valid
+    pub fn set(plan_id: &str, plan: Plan) -> Result<Self, CatalogError> {
+        let problems = plan.problems(plan_id);
+
+        if !problems.is_empty() {
+            return Err(CatalogError::Invalid(problems));
+        }
+
+        let _guard = CATALOG_LOCK.lock().unwrap();
+        let mut catalog = ProductCatalog::load()?;
+
+        catalog.plans.insert(plan_id.to_string(), plan);
+        catalog.version = catalog.hash()?;
+        catalog.save()?;
+
+        Ok(catalog)
+    }
+
+    pub fn remove(plan_id: &str) -> Result<Option<Self>, CatalogError> {
+        let _guard = CATALOG_LOCK.lock().unwrap();
+        let mut catalog = ProductCatalog::load()?;
+
+        if catalog.plans.remove(plan_id).is_none() {
+            return Ok(None);
+        }
+
+        // refused if an alias still points at it
+        catalog.validate()?;
+        catalog.version = catalog.hash()?;
+        catalog.save()?;
+
+        Ok(Some(catalog))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> PlanIdRules {
+        let mut rules: PlanIdRules = toml::from_str(text).unwrap();
+        assert!(rules.compile().is_empty());
+
+        rules
+    }
+
+    #[test]
+    fn strips_the_first_matching_prefix_and_suffix() {
+        let rules = rules(
+            r#"
+            prefixes = ["eugspfld-", "eug"]
+            suffixes = ["-12", "-24"]
+            "#,
+        );
+
+        assert_eq!(rules.normalize(" eugspfld-fiber-1g-12 "), "fiber-1g");
+        assert_eq!(rules.normalize("eugfiber-1g-24"), "fiber-1g");
+        // only one prefix and one suffix come off
+        assert_eq!(
+            rules.normalize("eugspfld-eug-fiber-1g-12-24"),
+            "eug-fiber-1g-12"
+        );
+        assert_eq!(rules.normalize("fiber-1g"), "fiber-1g");
+    }
+
+    #[test]
+    fn applies_rewrites_in_order() {
+        let rules = rules(
+            r#"
+            [[rewrites]]
+            pattern = "_"
+            replacement = "-"
+
+            [[rewrites]]
+            pattern = "-(\\d+)mb$"
+            replacement = "-${1}m"
+            "#,
+        );
+
+        assert_eq!(rules.normalize("res_fiber_100mb"), "res-fiber-100m");
+    }
+
+    #[test]
+    fn looks_up_aliases_last() {
+        let rules = rules(
+            r#"
+            suffixes = ["-12"]
+
+            [[rewrites]]
+            pattern = "^legacy-"
+            replacement = "old-"
+
+            [aliases]
+            old-dsl = "copper-25m"
+            "#,
+        );
+
+        assert_eq!(rules.normalize("legacy-dsl-12"), "copper-25m");
+        assert_eq!(rules.normalize("old-cable"), "old-cable");
+    }
+
+    #[test]
+    fn reports_invalid_rewrites() {
+        let mut rules: PlanIdRules = toml::from_str(
+            r#"
+            [[rewrites]]
+            pattern = "("
+            replacement = ""
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.compile().len(), 1);
+    }
+}
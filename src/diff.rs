@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{link, MatchConfig};
+use crate::carryover::{fabric_address as address, PriorLinks};
+use crate::emerald::ChargeBeeSource;
+use crate::fcc::FccRecord;
+use crate::index::FccIndex;
+use crate::overrides::Overrides;
+use crate::reports::{fabric_diff_report, subscriber_changes_report};
+use crate::store::{FabricStore, StoreError};
+use crate::subscriber::{SourceError, Subscriber, SubscriberSource};
+
+#[derive(Debug)]
+pub enum DiffError {
+    Store(StoreError),
+    Source(SourceError),
+    Report(io::Error),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffError::Store(e) => write!(f, "{e}"),
+            DiffError::Source(e) => write!(f, "{e}"),
+            DiffError::Report(e) => write!(f, "unable to write the diff reports: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DiffError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DiffError::Store(e) => Some(e),
+            DiffError::Source(e) => Some(e),
+            DiffError::Report(e) => Some(e),
+        }
+    }
+}
+
+impl From<StoreError> for DiffError {
+    fn from(e: StoreError) -> Self {
+        DiffError::Store(e)
+    }
+}
+
+impl From<SourceError> for DiffError {
+    fn from(e: SourceError) -> Self {
+        DiffError::Source(e)
+    }
+}
+
+impl From<io::Error> for DiffError {
+    fn from(e: io::Error) -> Self {
+        DiffError::Report(e)
+    }
+}
+
+impl From<csv::Error> for DiffError {
+    fn from(e: csv::Error) -> Self {
+        DiffError::Report(e.into())
+    }
+}
+
+// What POST /fabric/diff takes. state and county_geoid narrow the location
+// changes (and the subscriber changes) to part of the footprint; setting
+// subscribers relinks the current ChargeBee subscriptions against both
+// releases to see whose location would move.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiffRequest {
+    pub from: i64,
+    pub to: i64,
+    pub state: Option<String>,
+    pub county_geoid: Option<String>,
+    #[serde(default)]
+    pub subscribers: bool,
+}
+
+impl DiffRequest {
+    fn in_scope(&self, record: &FccRecord) -> bool {
+        self.state
+            .as_deref()
+            .is_none_or(|state| record.state.eq_ignore_ascii_case(state))
+            && self
+                .county_geoid
+                .as_deref()
+                .is_none_or(|county| &*record.county_geoid == county)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    AddressChanged,
+    BslFlagChanged,
+    BlockChanged,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "added"),
+            ChangeKind::Removed => write!(f, "removed"),
+            ChangeKind::AddressChanged => write!(f, "address_changed"),
+            ChangeKind::BslFlagChanged => write!(f, "bsl_flag_changed"),
+            ChangeKind::BlockChanged => write!(f, "block_changed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LocationChange {
+    pub location_id: String,
+    pub kind: ChangeKind,
+    pub state: String,
+    pub county_geoid: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberChangeKind {
+    Relinked,
+    Unlinked,
+}
+
+impl fmt::Display for SubscriberChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriberChangeKind::Relinked => write!(f, "relinked"),
+            SubscriberChangeKind::Unlinked => write!(f, "unlinked"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriberChange {
+    pub subscription_id: String,
+    pub customer_id: Option<String>,
+    pub from_location_id: String,
+    pub to_location_id: Option<String>,
+    pub kind: SubscriberChangeKind,
+}
+
+// written to output/reports/fabric_diff-<uuid>.json alongside the CSVs
+#[derive(Debug, Default, Serialize)]
+pub struct DiffSummary {
+    pub from: i64,
+    pub to: i64,
+    pub from_release_date: String,
+    pub to_release_date: String,
+    pub state: Option<String>,
+    pub county_geoid: Option<String>,
+    pub added: usize,
+    pub removed: usize,
+    pub address_changed: usize,
+    pub bsl_flag_changed: usize,
+    pub block_changed: usize,
+    pub subscribers_relinked: Option<usize>,
+    pub subscribers_unlinked: Option<usize>,
+}
+
+fn change(record: &FccRecord, kind: ChangeKind, from: String, to: String) -> LocationChange {
+    LocationChange {
+        location_id: record.location_id.clone(),
+        kind,
+        state: record.state.to_string(),
+        county_geoid: record.county_geoid.to_string(),
+        from,
+        to,
+    }
+}
+
+// What changed for one location_id between the releases, in ChangeKind
+// order. A location is in scope if either release has it in scope.
+fn compare(
+    old: Option<&FccRecord>,
+    new: Option<&FccRecord>,
+    request: &DiffRequest,
+    changes: &mut Vec<LocationChange>,
+) {
+    if !old.is_some_and(|old| request.in_scope(old))
+        && !new.is_some_and(|new| request.in_scope(new))
+    {
+        return;
+    }
+
+    let (old, new) = match (old, new) {
+        (Some(old), Some(new)) => (old, new),
+        (None, Some(new)) => {
+            changes.push(change(new, ChangeKind::Added, String::new(), address(new)));
+            return;
+        }
+        (Some(old), None) => {
+            changes.push(change(
+                old,
+                ChangeKind::Removed,
+                address(old),
+                String::new(),
+            ));
+            return;
+        }
+        (None, None) => return,
+    };
+
+    if address(old) != address(new) {
+        changes.push(change(
+            new,
+            ChangeKind::AddressChanged,
+            address(old),
+            address(new),
+        ));
+    }
+
+    if old.bsl_flag != new.bsl_flag {
+        changes.push(change(
+            new,
+            ChangeKind::BslFlagChanged,
+            old.bsl_flag.to_string(),
+            new.bsl_flag.to_string(),
+        ));
+    }
+
+    if old.block_geoid != new.block_geoid {
+        changes.push(change(
+            new,
+            ChangeKind::BlockChanged,
+            old.block_geoid.clone(),
+            new.block_geoid.clone(),
+        ));
+    }
+}
+
+// A single pass over both releases in location_id order, so neither is
+// loaded into memory. Changes come back sorted by location_id then kind,
+// so diffs of the same two releases always produce the same file.
+// Addresses are compared after USPS normalization so a release that only
+// changes abbreviations or case doesn't flag every location.
+pub fn location_changes(
+    store: &FabricStore,
+    request: &DiffRequest,
+) -> Result<Vec<LocationChange>, StoreError> {
+    let mut changes = vec![];
+    store.visit_location_pairs(request.from, request.to, |old, new| {
+        compare(old, new, request, &mut changes)
+    })?;
+
+    Ok(changes)
+}
+
+// Subscribers linked against the old release whose link moves to another
+// location, or is lost, when linked against the new one. A subscriber is
+// in scope if either location is.
+pub fn subscriber_changes(
+    from: &FccIndex,
+    to: &FccIndex,
+    subscribers: Vec<Subscriber>,
+    request: &DiffRequest,
+) -> Vec<SubscriberChange> {
+    let overrides = Overrides::load().unwrap_or_else(|e| {
+        log::error!("unable to load overrides: {e}");
+        Overrides::default()
+    });
+    let config = MatchConfig::from_env();
+    // links carried over from earlier runs would hide exactly the changes
+    // this is looking for, so both sides are matched fresh
+    let prior = PriorLinks::default();
+
+    let before = link(from, subscribers.clone(), &overrides, &prior, &config).linked;
+    let after: HashMap<String, FccRecord> = link(to, subscribers, &overrides, &prior, &config)
+        .linked
+        .into_iter()
+        .filter_map(|link| Some((link.subscriber.subscription_id.clone()?, link.fcc)))
+        .collect();
+
+    let mut changes: Vec<SubscriberChange> = before
+        .into_iter()
+        .filter_map(|link| {
+            let subscription_id = link.subscriber.subscription_id.clone()?;
+            let moved_to = after.get(&subscription_id);
+
+            if moved_to.is_some_and(|record| record.location_id == link.fcc.location_id) {
+                return None;
+            }
+
+            if !request.in_scope(&link.fcc) && !moved_to.is_some_and(|r| request.in_scope(r)) {
+                return None;
+            }
+
+            Some(SubscriberChange {
+                subscription_id,
+                customer_id: link.subscriber.customer_id.clone(),
+                from_location_id: link.fcc.location_id.clone(),
+                to_location_id: moved_to.map(|record| record.location_id.clone()),
+                kind: match moved_to {
+                    Some(_) => SubscriberChangeKind::Relinked,
+                    None => SubscriberChangeKind::Unlinked,
+                },
+            })
+        })
+        .collect();
+
+    changes.sort_by(|a, b| a.subscription_id.cmp(&b.subscription_id));
+
+    changes
+}
+
+pub fn diff(uuid: &str, request: &DiffRequest) -> Result<DiffSummary, DiffError> {
+    let store = FabricStore::open()?;
+    let from_release = store
+        .release(request.from)?
+        .ok_or(StoreError::NotFound(request.from))?;
+    let to_release = store
+        .release(request.to)?
+        .ok_or(StoreError::NotFound(request.to))?;
+
+    let changes = location_changes(&store, request)?;
+    fabric_diff_report(uuid, &changes)?;
+
+    let count = |kind| changes.iter().filter(|change| change.kind == kind).count();
+    let mut summary = DiffSummary {
+        from: from_release.id,
+        to: to_release.id,
+        from_release_date: from_release.release_date,
+        to_release_date: to_release.release_date,
+        state: request.state.clone(),
+        county_geoid: request.county_geoid.clone(),
+        added: count(ChangeKind::Added),
+        removed: count(ChangeKind::Removed),
+        address_changed: count(ChangeKind::AddressChanged),
+        bsl_flag_changed: count(ChangeKind::BslFlagChanged),
+        block_changed: count(ChangeKind::BlockChanged),
+        ..DiffSummary::default()
+    };
+
+    if request.subscribers {
+        // relinking needs both releases' match keys, so only this part
+        // loads them
+        let from = FccIndex::load(&store, from_release.id)?;
+        let to = FccIndex::load(&store, to_release.id)?;
+        let subscribers = subscriber_changes(
+            &from,
+            &to,
+            ChargeBeeSource::default().subscribers()?,
+            request,
+        );
+        subscriber_changes_report(uuid, &subscribers)?;
+
+        let count = |kind| {
+            subscribers
+                .iter()
+                .filter(|change| change.kind == kind)
+                .count()
+        };
+        summary.subscribers_relinked = Some(count(SubscriberChangeKind::Relinked));
+        summary.subscribers_unlinked = Some(count(SubscriberChangeKind::Unlinked));
+    }
+
+    fs::write(
+        format!("output/reports/fabric_diff-{uuid}.json"),
+        serde_json::to_vec(&summary).map_err(io::Error::from)?,
+    )?;
+
+    Ok(summary)
+}
@@ -1,15 +1,35 @@
+use chrono::NaiveDate;
 use core::time;
-use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, thread};
+use std::{fmt, sync::OnceLock, thread};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+use crate::snapshot::Snapshot;
+use crate::subscriber::{
+    self, CustomerClass, SourceError, Subscriber, SubscriberSource, SubscriberStatus,
+};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub enum ServiceMedium {
     Fiber,
     Wireless,
     Copper,
 }
 
+impl ServiceMedium {
+    // the BDC technology codes we can deliver over
+    pub fn from_technology_code(technology_code: u8) -> Option<Self> {
+        match technology_code {
+            10 => Some(ServiceMedium::Copper),
+            50 => Some(ServiceMedium::Fiber),
+            70..=72 => Some(ServiceMedium::Wireless),
+            _ => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct ServiceProfile {
     pub medium: ServiceMedium,
@@ -61,85 +81,6 @@ pub enum ProductType {
     Admin,
 }
 
-lazy_static! {
-    pub static ref PRODUCT_CODES: HashMap<String, ProductType> = HashMap::from([
-        (
-            "2g-commercial-fiber".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 2000, 2000, 10000, 10000).into())
-        ),
-        (
-            "fttp1000".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 1000, 1000, 1000, 1000).into())
-        ),
-        (
-            "fttp800".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 800, 800, 1000, 1000).into())
-        ),
-        (
-            "fttp400".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 400, 400, 1000, 1000).into())
-        ),
-        (
-            "fttp250".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 250, 250, 1000, 1000).into())
-        ),
-        (
-            "fttp100".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 100, 100, 1000, 1000).into())
-        ),
-        (
-            "fttp25".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 25, 25, 1000, 1000).into())
-        ),
-        (
-            "fw25".to_string(),
-            ProductType::Internet((ServiceMedium::Wireless, 70, 25, 25, 100, 100).into())
-        ),
-        (
-            "fw50".to_string(),
-            ProductType::Internet((ServiceMedium::Wireless, 70, 50, 50, 100, 100).into())
-        ),
-        (
-            "fw75".to_string(),
-            ProductType::Internet((ServiceMedium::Wireless, 70, 75, 75, 100, 100).into())
-        ),
-        (
-            "fw100".to_string(),
-            ProductType::Internet((ServiceMedium::Wireless, 70, 100, 100, 100, 100).into())
-        ),
-        (
-            "ens1g".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 1000, 1000, 1000, 1000).into())
-        ),
-        (
-            "enscustom".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 1000, 1000, 1000, 1000).into())
-        ),
-        (
-            "ens100mbps".to_string(),
-            ProductType::Internet((ServiceMedium::Fiber, 50, 100, 100, 100, 100).into())
-        ),
-        (
-            "gf100".to_string(),
-            ProductType::Internet((ServiceMedium::Copper, 10, 100, 100, 100, 100).into())
-        ),
-        ("voipfax".to_string(), ProductType::Voip),
-        ("voippbxr".to_string(), ProductType::Voip),
-        ("voiprpxr".to_string(), ProductType::Voip),
-        ("voipbus".to_string(), ProductType::Voip),
-        ("fax2email".to_string(), ProductType::Fax),
-        ("installation-quote".to_string(), ProductType::Admin),
-        ("paymentagreement".to_string(), ProductType::Admin),
-        ("pre-reg".to_string(), ProductType::Admin),
-        ("service-call-quote".to_string(), ProductType::Admin),
-        ("acp".to_string(), ProductType::Admin),
-        ("ipv427".to_string(), ProductType::Admin),
-        ("ipv428".to_string(), ProductType::Admin),
-        ("ipv429".to_string(), ProductType::Admin),
-        ("ipstatic".to_string(), ProductType::Admin),
-    ]);
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum SubscriptionObject {
@@ -214,7 +155,7 @@ pub struct PaymentMethod {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Customer {
-    id: Option<String>,
+    pub id: Option<String>,
     first_name: Option<String>,
     last_name: Option<String>,
     email: Option<String>,
@@ -274,7 +215,7 @@ pub struct Card {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Subscription {
-    id: Option<String>,
+    pub id: Option<String>,
     pub plan_id: Option<String>,
     plan_quantity: Option<u8>,
     plan_unit_price: Option<u32>,
@@ -286,6 +227,7 @@ pub struct Subscription {
     status: Option<String>,
     current_term_start: Option<u64>,
     current_term_end: Option<u64>,
+    cancelled_at: Option<u64>,
     next_billing_at: Option<u64>,
     created_at: Option<u64>,
     started_at: Option<u64>,
@@ -315,49 +257,560 @@ pub struct SubscriptionApiItem {
     card: Option<Card>,
 }
 
+// cf_residentialbusiness is typed in by hand, misspellings and all
+fn customer_class(text: &str) -> CustomerClass {
+    match text.trim().to_uppercase().as_str() {
+        "RESIDENTIAL" | "RESIDENTAIL" | "RRESIDENTIAL" => CustomerClass::Residential,
+        _ => CustomerClass::Business,
+    }
+}
+
+fn subscriber_status(text: &str) -> Option<SubscriberStatus> {
+    match text {
+        "future" => Some(SubscriberStatus::Future),
+        "in_trial" => Some(SubscriberStatus::InTrial),
+        "active" => Some(SubscriberStatus::Active),
+        "non_renewing" => Some(SubscriberStatus::NonRenewing),
+        "paused" => Some(SubscriberStatus::Paused),
+        "cancelled" => Some(SubscriberStatus::Cancelled),
+        _ => None,
+    }
+}
+
+impl From<SubscriptionApiItem> for Subscriber {
+    fn from(item: SubscriptionApiItem) -> Self {
+        let SubscriptionApiItem {
+            subscription,
+            customer,
+            ..
+        } = item;
+
+        Subscriber {
+            subscription_id: subscription.id,
+            customer_id: customer.id,
+            plan_id: subscription.plan_id,
+            service_address: customer.cf_service_address,
+            service_city_st_zip: customer.cf_service_city_st_zip,
+            census_block: customer.cf_census_block_no,
+            // ChargeBee doesn't keep coordinates; the nearest tier geocodes
+            // the service address instead
+            latitude: None,
+            longitude: None,
+            class: customer
+                .cf_residentialbusiness
+                .as_deref()
+                .map(customer_class),
+            status: subscription.status.as_deref().and_then(subscriber_status),
+            started_at: subscription.started_at.map(|at| at as i64),
+            activated_at: subscription.activated_at.map(|at| at as i64),
+            // older cancellations can be missing cancelled_at; the last
+            // update is the closest record of when it happened
+            cancelled_at: subscription
+                .cancelled_at
+                .or(match subscription.status.as_deref() {
+                    Some("cancelled") => subscription.updated_at,
+                    _ => None,
+                })
+                .map(|at| at as i64),
+            current_term_end: subscription.current_term_end.map(|at| at as i64),
+            ..Subscriber::default()
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ApiResponse {
     list: Vec<SubscriptionApiItem>,
     next_offset: Option<String>,
 }
 
-pub fn retrieve_subscriptions() -> Vec<SubscriptionApiItem> {
+#[derive(Debug)]
+pub enum ChargeBeeError {
+    // ChargeBee turned the API_KEY away (401 or 403)
+    Auth(u16),
+    // still rate limited after every retry
+    RateLimited,
+    // any other response ChargeBee wouldn't serve, with its message
+    Status {
+        status: u16,
+        message: String,
+    },
+    // no response at all after every retry
+    Request(reqwest::Error),
+    // the response didn't match ApiResponse; path is where in the JSON
+    Decode {
+        path: String,
+        error: serde_json::Error,
+    },
+    // a Retrieval that was cancelled, or ran past its timeout
+    Cancelled,
+    TimedOut(time::Duration),
+}
+
+impl fmt::Display for ChargeBeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChargeBeeError::Auth(status) => {
+                write!(f, "ChargeBee rejected the API_KEY ({status})")
+            }
+            ChargeBeeError::RateLimited => write!(
+                f,
+                "ChargeBee is still rate limiting after {MAX_ATTEMPTS} attempts"
+            ),
+            ChargeBeeError::Status { status, message } => {
+                write!(f, "ChargeBee returned {status}: {message}")
+            }
+            ChargeBeeError::Request(e) => write!(f, "unable to reach ChargeBee: {e}"),
+            ChargeBeeError::Decode { path, error } => {
+                write!(f, "unexpected ChargeBee response at {path}: {error}")
+            }
+            ChargeBeeError::Cancelled => write!(f, "ChargeBee retrieval was cancelled"),
+            ChargeBeeError::TimedOut(timeout) => write!(
+                f,
+                "ChargeBee retrieval didn't finish within {}s",
+                timeout.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChargeBeeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChargeBeeError::Request(e) => Some(e),
+            ChargeBeeError::Decode { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// a ChargeBee list filter, e.g. ("status[is]", "active")
+pub type Filter = (&'static str, String);
+
+const MAX_ATTEMPTS: u32 = 5;
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(60);
+
+fn active_filters() -> Vec<Filter> {
+    vec![("status[is]", "active".to_string())]
+}
+
+// ChargeBee only knows each subscription's current state, so the set as
+// of a past date is rebuilt from everything that could have been live
+// then: what's still on the books plus whatever was cancelled after the
+// cutoff. Cancellations are found by updated_at rather than cancelled_at,
+// which ChargeBee doesn't always set; a cancellation after the cutoff was
+// necessarily updated after it. Subscriber::subscribed_on then sorts out
+// which of them were live.
+fn as_of_filters(as_of: NaiveDate) -> [Vec<Filter>; 2] {
+    let cutoff = subscriber::cutoff(as_of);
+
+    [
+        vec![
+            (
+                "status[in]",
+                r#"["active","in_trial","non_renewing","paused"]"#.to_string(),
+            ),
+            ("started_at[before]", cutoff.to_string()),
+        ],
+        vec![
+            ("status[is]", "cancelled".to_string()),
+            ("updated_at[after]", (cutoff - 1).to_string()),
+        ],
+    ]
+}
+
+fn page_query(offset: Option<String>, filters: &[Filter]) -> Vec<(&'static str, String)> {
+    let mut query: Vec<(&str, String)> = vec![("limit", "100".to_string())];
+    query.extend(filters.iter().cloned());
+    query.extend(offset.map(|offset| ("offset", offset)));
+
+    query
+}
+
+pub fn retrieve_subscriptions() -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    retrieve_filtered(&active_filters())
+}
+
+pub fn retrieve_subscriptions_as_of(
+    as_of: NaiveDate,
+) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    let [current, cancelled] = as_of_filters(as_of);
+
+    let mut subscriptions = retrieve_filtered(&current)?;
+    subscriptions.append(&mut retrieve_filtered(&cancelled)?);
+
+    Ok(subscriptions)
+}
+
+fn client() -> Result<reqwest::blocking::Client, ChargeBeeError> {
+    reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(ChargeBeeError::Request)
+}
+
+fn retrieve_filtered(filters: &[Filter]) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    let client = client()?;
     let mut subscriptions: Vec<SubscriptionApiItem> = vec![];
-    let mut offset: Option<String> = {
-        let mut page = retrieve_subscription_page(None);
+    let mut offset: Option<String> = None;
+
+    loop {
+        let mut page = retrieve_page(&client, offset, filters)?;
         subscriptions.append(&mut page.list);
-        page.next_offset
-    };
 
-    while offset.is_some() {
-        thread::sleep(time::Duration::from_millis(1000));
-        let mut page = retrieve_subscription_page(offset);
+        offset = page.next_offset;
+
+        if offset.is_none() {
+            return Ok(subscriptions);
+        }
+    }
+}
+
+pub fn retrieve_subscription_page(
+    offset: Option<String>,
+    filters: &[Filter],
+) -> Result<ApiResponse, ChargeBeeError> {
+    retrieve_page(&client()?, offset, filters)
+}
+
+// 2, 4, 8, ... seconds after each failed attempt, capped at MAX_BACKOFF
+fn backoff(attempt: u32) -> time::Duration {
+    time::Duration::from_secs(1 << attempt.min(6)).min(MAX_BACKOFF)
+}
+
+enum Outcome {
+    Success,
+    Retry(time::Duration),
+    Fail,
+}
+
+// What to do with a response, shared by the blocking and async clients.
+// Rate limits (429) and server errors are retried up to MAX_ATTEMPTS
+// times, a 429 after the Retry-After (in seconds) ChargeBee sends with it;
+// anything else fails straight away.
+fn outcome(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    attempt: u32,
+) -> Result<Outcome, ChargeBeeError> {
+    if status.is_success() {
+        return Ok(Outcome::Success);
+    }
+
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(ChargeBeeError::Auth(status.as_u16()));
+    }
+
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        if attempt >= MAX_ATTEMPTS {
+            return Err(ChargeBeeError::RateLimited);
+        }
+
+        let wait = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(time::Duration::from_secs)
+            .unwrap_or_else(|| backoff(attempt))
+            .min(MAX_BACKOFF);
+        log::warn!("ChargeBee rate limited, waiting {}s", wait.as_secs());
+
+        return Ok(Outcome::Retry(wait));
+    }
+
+    if status.is_server_error() && attempt < MAX_ATTEMPTS {
+        log::warn!("ChargeBee returned {status}, retrying");
+        return Ok(Outcome::Retry(backoff(attempt)));
+    }
+
+    Ok(Outcome::Fail)
+}
+
+// requests that never got a response are retried like server errors
+fn request_failed(e: reqwest::Error, attempt: u32) -> Result<time::Duration, ChargeBeeError> {
+    if attempt >= MAX_ATTEMPTS {
+        return Err(ChargeBeeError::Request(e));
+    }
+
+    log::warn!("ChargeBee request failed, retrying: {e}");
+    Ok(backoff(attempt))
+}
+
+// ChargeBee's error responses carry a message; fall back to the raw body
+fn status_error(status: reqwest::StatusCode, body: &str) -> ChargeBeeError {
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|error| error["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+
+    ChargeBeeError::Status {
+        status: status.as_u16(),
+        message,
+    }
+}
+
+fn retrieve_page(
+    client: &reqwest::blocking::Client,
+    offset: Option<String>,
+    filters: &[Filter],
+) -> Result<ApiResponse, ChargeBeeError> {
+    let query = page_query(offset, filters);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let result = client
+            .get(&*crate::API_URL)
+            .query(&query)
+            .basic_auth(&*crate::API_KEY, None::<String>)
+            .send();
+
+        let wait = match result {
+            Err(e) => request_failed(e, attempt)?,
+            Ok(response) => match outcome(response.status(), response.headers(), attempt)? {
+                Outcome::Success => {
+                    return decode(&response.text().map_err(ChargeBeeError::Request)?)
+                }
+                Outcome::Retry(wait) => wait,
+                Outcome::Fail => {
+                    let status = response.status();
+                    return Err(status_error(status, &response.text().unwrap_or_default()));
+                }
+            },
+        };
+
+        thread::sleep(wait);
+    }
+}
+
+fn decode(body: &str) -> Result<ApiResponse, ChargeBeeError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| ChargeBeeError::Decode {
+        path: e.path().to_string(),
+        error: e.into_inner(),
+    })
+}
+
+// The same retrieval on the async reqwest client, for callers already on
+// a Tokio runtime. Nothing here blocks, so it's safe to await from Rocket.
+pub async fn retrieve_subscriptions_async() -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    retrieve_filtered_async(&active_filters()).await
+}
+
+pub async fn retrieve_subscriptions_as_of_async(
+    as_of: NaiveDate,
+) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    let [current, cancelled] = as_of_filters(as_of);
+
+    let mut subscriptions = retrieve_filtered_async(&current).await?;
+    subscriptions.append(&mut retrieve_filtered_async(&cancelled).await?);
+
+    Ok(subscriptions)
+}
+
+fn async_client() -> Result<reqwest::Client, ChargeBeeError> {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(ChargeBeeError::Request)
+}
+
+async fn retrieve_filtered_async(
+    filters: &[Filter],
+) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+    let client = async_client()?;
+    let mut subscriptions: Vec<SubscriptionApiItem> = vec![];
+    let mut offset: Option<String> = None;
+
+    loop {
+        let mut page = retrieve_page_async(&client, offset, filters).await?;
         subscriptions.append(&mut page.list);
+
         offset = page.next_offset;
+
+        if offset.is_none() {
+            return Ok(subscriptions);
+        }
+    }
+}
+
+pub async fn retrieve_subscription_page_async(
+    offset: Option<String>,
+    filters: &[Filter],
+) -> Result<ApiResponse, ChargeBeeError> {
+    retrieve_page_async(&async_client()?, offset, filters).await
+}
+
+async fn retrieve_page_async(
+    client: &reqwest::Client,
+    offset: Option<String>,
+    filters: &[Filter],
+) -> Result<ApiResponse, ChargeBeeError> {
+    let query = page_query(offset, filters);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let result = client
+            .get(&*crate::API_URL)
+            .query(&query)
+            .basic_auth(&*crate::API_KEY, None::<String>)
+            .send()
+            .await;
+
+        let wait = match result {
+            Err(e) => request_failed(e, attempt)?,
+            Ok(response) => match outcome(response.status(), response.headers(), attempt)? {
+                Outcome::Success => {
+                    return decode(&response.text().await.map_err(ChargeBeeError::Request)?)
+                }
+                Outcome::Retry(wait) => wait,
+                Outcome::Fail => {
+                    let status = response.status();
+                    return Err(status_error(
+                        status,
+                        &response.text().await.unwrap_or_default(),
+                    ));
+                }
+            },
+        };
+
+        tokio::time::sleep(wait).await;
     }
+}
 
-    subscriptions
+// A retrieval running as a task on a Tokio runtime. It ends with
+// ChargeBeeError::Cancelled once its token is cancelled, or TimedOut if it
+// runs past the timeout; either way the task stops at its next await
+// rather than running on in the background.
+pub struct Retrieval {
+    runtime: Handle,
+    task: JoinHandle<Result<Vec<SubscriptionApiItem>, ChargeBeeError>>,
+    cancel: CancellationToken,
 }
 
-pub fn retrieve_subscription_page(offset: Option<String>) -> ApiResponse {
-    let client = reqwest::blocking::Client::new();
+impl Retrieval {
+    pub fn spawn(
+        runtime: &Handle,
+        as_of: Option<NaiveDate>,
+        timeout: time::Duration,
+        cancel: CancellationToken,
+    ) -> Self {
+        let token = cancel.clone();
+        let task = runtime.spawn(async move {
+            let retrieval = async {
+                match as_of {
+                    Some(as_of) => retrieve_subscriptions_as_of_async(as_of).await,
+                    None => retrieve_subscriptions_async().await,
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => Err(ChargeBeeError::Cancelled),
+                result = tokio::time::timeout(timeout, retrieval) => {
+                    result.unwrap_or(Err(ChargeBeeError::TimedOut(timeout)))
+                }
+            }
+        });
 
-    let offset = {
-        if let Some(offset) = offset {
-            format!("&offset={offset}")
-        } else {
-            "".to_string()
+        Retrieval {
+            runtime: runtime.clone(),
+            task,
+            cancel,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub async fn join(self) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(ChargeBeeError::Cancelled),
+        }
+    }
+
+    // for callers off the runtime, like the pipeline's worker thread; this
+    // panics if called from async code, which should await join instead
+    pub fn wait(self) -> Result<Vec<SubscriptionApiItem>, ChargeBeeError> {
+        let runtime = self.runtime.clone();
+        runtime.block_on(self.join())
+    }
+}
+
+// With as_of set, the subscribers as of the end of that day rather than
+// the ones active now. A source made with new saves what ChargeBee returns
+// as a snapshot before it's used, so the run can be reproduced; the default
+// one, for lookups that don't produce a filing, doesn't.
+//
+// Given a runtime, the subscriptions are fetched by a Retrieval on it,
+// which can be cancelled through the source's token and gives up after
+// CHARGEBEE_TIMEOUT; otherwise they're fetched on the calling thread.
+#[derive(Debug, Clone, Default)]
+pub struct ChargeBeeSource {
+    pub as_of: Option<NaiveDate>,
+    save_snapshot: bool,
+    runtime: Option<Handle>,
+    cancel: CancellationToken,
+    snapshot: OnceLock<String>,
+}
+
+impl ChargeBeeSource {
+    pub fn new(as_of: Option<NaiveDate>) -> Self {
+        ChargeBeeSource {
+            as_of,
+            save_snapshot: true,
+            ..ChargeBeeSource::default()
         }
-    };
-
-    client
-        .get(format!(
-            "{}?limit=100&status[is]=active{offset}",
-            &*crate::API_URL
-        ))
-        .basic_auth(&*crate::API_KEY, None::<String>)
-        .send()
-        .unwrap()
-        .json::<ApiResponse>()
-        .unwrap()
+    }
+
+    pub fn on_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+impl SubscriberSource for ChargeBeeSource {
+    fn name(&self) -> &str {
+        "chargebee"
+    }
+
+    fn snapshot_id(&self) -> Option<String> {
+        self.snapshot.get().cloned()
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        let subscriptions = match (&self.runtime, self.as_of) {
+            (Some(runtime), as_of) => Retrieval::spawn(
+                runtime,
+                as_of,
+                time::Duration::from_secs(*crate::CHARGEBEE_TIMEOUT),
+                self.cancel.clone(),
+            )
+            .wait()?,
+            (None, Some(as_of)) => retrieve_subscriptions_as_of(as_of)?,
+            (None, None) => retrieve_subscriptions()?,
+        };
+
+        let subscriptions = if self.save_snapshot {
+            let snapshot = Snapshot::new(subscriptions, self.as_of);
+            snapshot.save()?;
+            self.snapshot.set(snapshot.id).ok();
+            snapshot.subscriptions
+        } else {
+            subscriptions
+        };
+
+        Ok(subscriptions.into_iter().map(Subscriber::from).collect())
+    }
 }
@@ -1,7 +1,121 @@
-use csv::ReaderBuilder;
-use serde::Deserialize;
+use csv::{ByteRecord, ReaderBuilder};
+use serde::{Deserialize, Serialize};
 
+use crate::normalize::{normalize_address, normalize_city, NormalizedAddress};
+use crate::schema::{detect_schema, FabricSchema, SchemaMismatch};
+
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::sync::Arc;
+
+use flate2::read::MultiGzDecoder;
+use zip::{result::ZipError, ZipArchive};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+// a badly broken upload could reject every one of millions of rows; past
+// this many only the count is kept
+const MAX_REJECTED_ROWS: usize = 10_000;
+
+#[derive(Debug)]
+pub enum IngestError {
+    Open { filename: String, source: io::Error },
+    Archive(ZipError),
+    NoCsv,
+    Header(csv::Error),
+    Schema(SchemaMismatch),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Open { filename, source } => {
+                write!(f, "unable to open Fabric file {filename}: {source}")
+            }
+            IngestError::Archive(e) => write!(f, "unable to read the Fabric archive: {e}"),
+            IngestError::NoCsv => write!(f, "the Fabric archive doesn't contain any CSV files"),
+            IngestError::Header(e) => write!(f, "unable to read the Fabric header: {e}"),
+            IngestError::Schema(mismatch) => write!(f, "{mismatch}"),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IngestError::Open { source, .. } => Some(source),
+            IngestError::Archive(e) => Some(e),
+            IngestError::NoCsv => None,
+            IngestError::Header(e) => Some(e),
+            IngestError::Schema(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedRow {
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub column: Option<String>,
+    pub error: String,
+}
+
+// total_rows counts every data row read; rows that failed to read or
+// deserialize are counted in rejected_rows. A read error that stops the
+// file part way through is recorded in aborted, which usually means the
+// upload was truncated. An archive can hold several state files, which
+// are listed in files and totalled together.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestReport {
+    pub fabric_version: String,
+    pub files: Vec<String>,
+    pub total_rows: u64,
+    pub accepted_rows: u64,
+    pub rejected_rows: u64,
+    pub rejected: Vec<RejectedRow>,
+    pub aborted: Option<String>,
+}
+
+impl IngestReport {
+    fn reject(&mut self, row: RejectedRow) {
+        self.rejected_rows += 1;
+
+        if self.rejected.len() < MAX_REJECTED_ROWS {
+            self.rejected.push(row);
+        }
+    }
+
+    fn merge(&mut self, file: &str, other: IngestReport) {
+        if self.fabric_version.is_empty() {
+            self.fabric_version = other.fabric_version;
+        } else if !self
+            .fabric_version
+            .split(", ")
+            .any(|version| version == other.fabric_version)
+        {
+            self.fabric_version = format!("{}, {}", self.fabric_version, other.fabric_version);
+        }
+
+        self.files.push(file.to_string());
+        self.total_rows += other.total_rows;
+        self.accepted_rows += other.accepted_rows;
+        self.rejected_rows += other.rejected_rows - other.rejected.len() as u64;
+
+        for row in other.rejected {
+            self.reject(RejectedRow {
+                file: Some(file.to_string()),
+                ..row
+            });
+        }
+
+        if let Some(aborted) = other.aborted {
+            self.aborted = Some(format!("{file}: {aborted}"));
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Clone)]
 struct FccByteRecord<'a> {
@@ -18,17 +132,19 @@ struct FccByteRecord<'a> {
     address_confidence_code: u64,
     county_geoid: &'a [u8],
     block_geoid: String,
-    h3_9: String,
+    h3_9: &'a [u8],
     latitude: f64,
     longitude: f64,
 }
 
+// state, city, county_geoid and h3_9 repeat across huge numbers of rows in
+// a statewide Fabric, so each distinct value is stored once and shared
 #[derive(Debug, Deserialize, Clone)]
 pub struct FccRecord {
     pub location_id: String,
     pub address_primary: Option<String>,
-    pub city: Option<String>,
-    pub state: String,
+    pub city: Option<Arc<str>>,
+    pub state: Arc<str>,
     pub zip: Option<u32>,
     pub zip_suffix: Option<u32>,
     pub unit_count: u64,
@@ -36,20 +152,39 @@ pub struct FccRecord {
     pub building_type_code: char,
     pub land_use_code: u64,
     pub address_confidence_code: u64,
-    pub county_geoid: String,
+    pub county_geoid: Arc<str>,
     pub block_geoid: String,
-    pub h3_9: String,
+    pub h3_9: Arc<str>,
     pub latitude: f64,
     pub longitude: f64,
 }
 
-impl<'a> From<FccByteRecord<'a>> for FccRecord {
-    fn from(byte_record: FccByteRecord) -> Self {
+#[derive(Default, Debug)]
+pub(crate) struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    fn intern(&mut self, bytes: &[u8]) -> Arc<str> {
+        self.intern_str(&utf8(bytes))
+    }
+
+    pub(crate) fn intern_str(&mut self, text: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(text) {
+            return existing.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(text);
+        self.strings.insert(interned.clone());
+        interned
+    }
+
+    fn record(&mut self, byte_record: FccByteRecord) -> FccRecord {
         FccRecord {
-            location_id: utf8(byte_record.clone().location_id),
-            address_primary: byte_record.clone().address_primary.map(utf8),
-            city: byte_record.clone().city.map(utf8),
-            state: utf8(byte_record.state),
+            location_id: utf8(byte_record.location_id),
+            address_primary: byte_record.address_primary.map(utf8),
+            city: byte_record.city.map(|city| self.intern(city)),
+            state: self.intern(byte_record.state),
             zip: byte_record.zip,
             zip_suffix: byte_record.zip_suffix,
             unit_count: byte_record.unit_count,
@@ -57,36 +192,223 @@ impl<'a> From<FccByteRecord<'a>> for FccRecord {
             building_type_code: byte_record.building_type_code,
             land_use_code: byte_record.land_use_code,
             address_confidence_code: byte_record.address_confidence_code,
-            county_geoid: utf8(byte_record.county_geoid),
+            county_geoid: self.intern(byte_record.county_geoid),
             block_geoid: byte_record.block_geoid,
-            h3_9: byte_record.h3_9,
+            h3_9: self.intern(byte_record.h3_9),
             latitude: byte_record.latitude,
             longitude: byte_record.longitude,
         }
     }
 }
 
+impl FccRecord {
+    pub fn normalized_address(&self) -> Option<NormalizedAddress> {
+        self.address_primary.as_deref().and_then(normalize_address)
+    }
+
+    pub fn normalized_city(&self) -> Option<String> {
+        self.city.as_deref().map(normalize_city)
+    }
+}
+
 fn utf8(bytes: &[u8]) -> String {
     encoding_rs::mem::decode_latin1(bytes).into_owned()
 }
 
-pub fn process_fcc_data(filename: String) -> Vec<FccRecord> {
-    let file = File::open(filename).unwrap();
-    let mut rdr = ReaderBuilder::new().from_reader(&file);
+// Rows are decoded one at a time as the iterator is advanced, so a Fabric
+// file never has to be held in memory as a whole.
+pub struct FabricReader<R: Read> {
+    reader: csv::Reader<R>,
+    schema: &'static FabricSchema,
+    headers: ByteRecord,
+    record: ByteRecord,
+    interner: Interner,
+    report: IngestReport,
+    done: bool,
+}
+
+impl<R: Read> FabricReader<R> {
+    // the header is checked against the known Fabric layouts up front so
+    // an unknown layout is rejected before any rows are read
+    pub fn new(source: R) -> Result<Self, IngestError> {
+        Self::with_interner(source, Interner::default())
+    }
 
-    rdr.byte_records()
-        .filter_map(|byte_record| {
-            if let Ok(byte_record) = byte_record {
-                match byte_record.deserialize::<FccByteRecord>(None) {
-                    Ok(fcc_record) => Some(fcc_record.into()),
-                    Err(e) => {
-                        log::error!("{e}");
-                        None
+    // for several files that share values, like the CSVs in one archive
+    fn with_interner(source: R, interner: Interner) -> Result<Self, IngestError> {
+        let mut reader = ReaderBuilder::new().from_reader(source);
+        let headers = reader.byte_headers().map_err(IngestError::Header)?.clone();
+        let schema = detect_schema(&headers).map_err(IngestError::Schema)?;
+
+        log::debug!("FABRIC VERSION {}", schema.version);
+
+        Ok(FabricReader {
+            reader,
+            schema,
+            headers: schema.field_headers(&headers),
+            record: ByteRecord::new(),
+            interner,
+            report: IngestReport {
+                fabric_version: schema.version.to_string(),
+                ..IngestReport::default()
+            },
+            done: false,
+        })
+    }
+
+    pub fn version(&self) -> &'static str {
+        self.schema.version
+    }
+
+    // only complete once the iterator has been run to the end
+    pub fn report(&self) -> &IngestReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> IngestReport {
+        self.report
+    }
+
+    fn into_parts(self) -> (IngestReport, Interner) {
+        (self.report, self.interner)
+    }
+
+    fn column(&mut self, field: Option<u64>) -> Option<String> {
+        let field = field? as usize;
+
+        match self.reader.byte_headers() {
+            Ok(headers) => headers.get(field).map(utf8),
+            Err(_) => Some(field.to_string()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for FabricReader<R> {
+    type Item = FccRecord;
+
+    fn next(&mut self) -> Option<FccRecord> {
+        while !self.done {
+            match self.reader.read_byte_record(&mut self.record) {
+                Ok(true) => {
+                    self.report.total_rows += 1;
+
+                    match self
+                        .record
+                        .deserialize::<FccByteRecord>(Some(&self.headers))
+                    {
+                        Ok(byte_record) => {
+                            self.report.accepted_rows += 1;
+                            return Some(self.interner.record(byte_record));
+                        }
+                        Err(e) => {
+                            let line = self.record.position().map(|position| position.line());
+                            let field = match e.kind() {
+                                csv::ErrorKind::Deserialize { err, .. } => err.field(),
+                                _ => None,
+                            };
+                            let column = self.column(field);
+
+                            self.report.reject(RejectedRow {
+                                file: None,
+                                line,
+                                column,
+                                error: e.to_string(),
+                            });
+                        }
                     }
                 }
-            } else {
-                None
+                Ok(false) => self.done = true,
+                // a read failure means the rest of the file can't be trusted
+                Err(e) if e.is_io_error() => {
+                    log::error!("{e}");
+                    self.report.aborted = Some(e.to_string());
+                    self.done = true;
+                }
+                Err(e) => {
+                    self.report.total_rows += 1;
+                    self.report.reject(RejectedRow {
+                        file: None,
+                        line: e.position().map(|position| position.line()),
+                        column: None,
+                        error: e.to_string(),
+                    });
+                }
             }
-        })
-        .collect()
+        }
+
+        None
+    }
+}
+
+fn visit_csv(
+    name: &str,
+    source: impl Read,
+    interner: &mut Interner,
+    report: &mut IngestReport,
+    visit: &mut impl FnMut(FccRecord),
+) -> Result<(), IngestError> {
+    let mut reader = FabricReader::with_interner(source, std::mem::take(interner))?;
+
+    for record in reader.by_ref() {
+        visit(record);
+    }
+
+    let (file_report, file_interner) = reader.into_parts();
+    *interner = file_interner;
+    report.merge(name, file_report);
+
+    Ok(())
+}
+
+// The FCC contractor usually sends the Fabric zipped, sometimes with a CSV
+// per state, so the container is sniffed from its magic bytes rather than
+// trusting a file name. Every CSV inside is streamed to visit in turn.
+pub fn process_fcc_data(
+    filename: String,
+    mut visit: impl FnMut(FccRecord),
+) -> Result<IngestReport, IngestError> {
+    let open_error = |source| IngestError::Open {
+        filename: filename.clone(),
+        source,
+    };
+
+    let mut file = File::open(&filename).map_err(open_error)?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic).map_err(open_error)?;
+    file.rewind().map_err(open_error)?;
+
+    let mut report = IngestReport::default();
+    let mut interner = Interner::default();
+
+    if magic[..read].starts_with(ZIP_MAGIC) {
+        let mut archive = ZipArchive::new(file).map_err(IngestError::Archive)?;
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).map_err(IngestError::Archive)?;
+            let name = entry.name().to_string();
+
+            if entry.is_dir() || !name.to_lowercase().ends_with(".csv") {
+                continue;
+            }
+
+            log::debug!("READING {name} FROM ARCHIVE");
+            visit_csv(&name, entry, &mut interner, &mut report, &mut visit)?;
+        }
+
+        if report.files.is_empty() {
+            return Err(IngestError::NoCsv);
+        }
+    } else if magic[..read].starts_with(GZIP_MAGIC) {
+        visit_csv(
+            &filename,
+            MultiGzDecoder::new(BufReader::new(file)),
+            &mut interner,
+            &mut report,
+            &mut visit,
+        )?;
+    } else {
+        visit_csv(&filename, file, &mut interner, &mut report, &mut visit)?;
+    }
+
+    Ok(report)
 }
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// Coordinates for a one line service address, for the nearest tier to fall
+// back on when the address tiers can't link a subscription.
+pub trait Geocoder: Send + Sync {
+    fn geocode(&self, address: &str) -> Option<(f64, f64)>;
+}
+
+#[derive(Debug, Deserialize)]
+struct CensusResponse {
+    result: CensusResult,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CensusResult {
+    address_matches: Vec<CensusMatch>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CensusMatch {
+    coordinates: CensusCoordinates,
+}
+
+#[derive(Debug, Deserialize)]
+struct CensusCoordinates {
+    x: f64,
+    y: f64,
+}
+
+// The Census Bureau's one line address geocoder, e.g.
+// https://geocoding.geo.census.gov/geocoder/locations/onelineaddress, or
+// anything at GEOCODER_URL that answers the same way. Answers are cached
+// so each address is only asked about once.
+pub struct CensusGeocoder {
+    url: String,
+    client: reqwest::blocking::Client,
+    cache: Mutex<HashMap<String, Option<(f64, f64)>>>,
+}
+
+impl CensusGeocoder {
+    pub fn new(url: String) -> Self {
+        CensusGeocoder {
+            url,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .expect("unable to build the geocoder client"),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn request(&self, address: &str) -> Option<(f64, f64)> {
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&[
+                ("address", address),
+                ("benchmark", "Public_AR_Current"),
+                ("format", "json"),
+            ])
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.json::<CensusResponse>());
+
+        match response {
+            Ok(response) => response
+                .result
+                .address_matches
+                .first()
+                .map(|found| (found.coordinates.y, found.coordinates.x)),
+            // a geocoder that's down only costs the fallback tier
+            Err(e) => {
+                log::warn!("unable to geocode {address}: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl Geocoder for CensusGeocoder {
+    fn geocode(&self, address: &str) -> Option<(f64, f64)> {
+        if let Some(coordinates) = self.cache.lock().unwrap().get(address) {
+            return *coordinates;
+        }
+
+        let coordinates = self.request(address);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(address.to_string(), coordinates);
+
+        coordinates
+    }
+}
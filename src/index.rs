@@ -0,0 +1,219 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    fcc::FccRecord,
+    normalize::NormalizedAddress,
+    store::{FabricStore, StoreError},
+};
+
+// size of the cells the coordinates are bucketed into; a hundredth of a
+// degree is around a kilometer, comfortably bigger than NEAREST_METERS
+const GRID_DEGREES: f64 = 0.01;
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+// how far a subscriber's coordinates can be from a BSL and still be
+// linked to it
+pub const NEAREST_METERS: f64 = 150.0;
+
+pub fn digits(text: &str) -> String {
+    text.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+// The key everything in the Fabric is bucketed under. A subscriber can
+// only ever link to a record that agrees on all three of these, so the
+// fuzzy street comparison only has to run against a handful of rows
+// instead of the whole file.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub struct AddressKey {
+    pub city: String,
+    pub house_number: String,
+    pub digits: String,
+}
+
+impl AddressKey {
+    pub fn new(address: &NormalizedAddress, city: &str) -> Self {
+        AddressKey {
+            city: city.to_string(),
+            house_number: address.number.clone(),
+            digits: address.digits(),
+        }
+    }
+}
+
+fn grid_cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (
+        (latitude / GRID_DEGREES).floor() as i32,
+        (longitude / GRID_DEGREES).floor() as i32,
+    )
+}
+
+pub fn great_circle_distance((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let delta_lat = lat2 - lat1;
+    let delta_lon = (lon2 - lon1).to_radians();
+
+    let a =
+        (delta_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+// A hash of a key, which is all the index keeps of it. Two keys can share
+// one, so whatever comes back for it is checked against the real key.
+fn fingerprint(key: &(impl Hash + ?Sized)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the positions filed under key, out of entries sorted by key then position
+fn positions<'a, K: Ord>(entries: &'a [(K, u32)], key: &K) -> impl Iterator<Item = usize> + 'a {
+    let start = entries.partition_point(|(other, _)| other < key);
+    let end = start + entries[start..].partition_point(|(other, _)| other == key);
+
+    entries[start..end]
+        .iter()
+        .map(|(_, position)| *position as usize)
+}
+
+// Each record is held once, with the values that repeat across a statewide
+// Fabric shared between rows (see fcc::Interner). Everything a record can
+// be looked up by is kept as a sorted list of key fingerprints and record
+// positions rather than a map of owned keys, so the lookups cost a few
+// dozen bytes a row. Sorting by key then position keeps each key's records
+// in Fabric order.
+#[derive(Default, Debug)]
+pub struct FccIndex {
+    records: Vec<FccRecord>,
+    addresses: Vec<(u64, u32)>,
+    cities: HashSet<String>,
+    locations: Vec<(u64, u32)>,
+    blocks: Vec<(u64, u32)>,
+    grid: Vec<((i32, i32), u32)>,
+}
+
+impl FccIndex {
+    pub fn new(records: impl IntoIterator<Item = FccRecord>) -> Self {
+        let mut index = FccIndex {
+            records: records.into_iter().collect(),
+            ..FccIndex::default()
+        };
+
+        for (position, record) in index.records.iter().enumerate() {
+            let position = position as u32;
+
+            if let Some(city) = record.normalized_city() {
+                if let Some(address) = record.normalized_address() {
+                    index
+                        .addresses
+                        .push((fingerprint(&AddressKey::new(&address, &city)), position));
+                }
+
+                index.cities.insert(city);
+            }
+
+            index
+                .locations
+                .push((fingerprint(record.location_id.as_str()), position));
+            index
+                .blocks
+                .push((fingerprint(&digits(&record.block_geoid)), position));
+
+            // only BSLs are eligible for the coordinate fallback
+            if record.bsl_flag {
+                index
+                    .grid
+                    .push((grid_cell(record.latitude, record.longitude), position));
+            }
+        }
+
+        index.addresses.sort_unstable();
+        index.locations.sort_unstable();
+        index.blocks.sort_unstable();
+        index.grid.sort_unstable();
+
+        index
+    }
+
+    // a stored release, read into memory once for the run
+    pub fn load(store: &FabricStore, release_id: i64) -> Result<Self, StoreError> {
+        let mut records = vec![];
+        store.visit_locations(release_id, |record| records.push(record))?;
+
+        Ok(FccIndex::new(records))
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn location(&self, location_id: &str) -> Option<&FccRecord> {
+        positions(&self.locations, &fingerprint(location_id))
+            .map(|position| &self.records[position])
+            .find(|record| record.location_id == location_id)
+    }
+
+    pub fn contains_city(&self, city: &str) -> bool {
+        self.cities.contains(city)
+    }
+
+    // candidates are returned in the order they appeared in the Fabric
+    // file so that "first match wins" behaves the same as a linear scan
+    pub fn candidates<'a>(
+        &'a self,
+        key: &'a AddressKey,
+    ) -> impl Iterator<Item = (&'a FccRecord, NormalizedAddress)> + 'a {
+        positions(&self.addresses, &fingerprint(key)).filter_map(move |position| {
+            let record = &self.records[position];
+            let address = record.normalized_address()?;
+            let city = record.normalized_city()?;
+
+            (AddressKey::new(&address, &city) == *key).then_some((record, address))
+        })
+    }
+
+    // every addressed record in a census block, regardless of city
+    pub fn block<'a>(
+        &'a self,
+        block_geoid: &str,
+    ) -> impl Iterator<Item = (&'a FccRecord, NormalizedAddress)> + 'a {
+        let block = digits(block_geoid);
+
+        positions(&self.blocks, &fingerprint(&block)).filter_map(move |position| {
+            let record = &self.records[position];
+
+            if digits(&record.block_geoid) != block {
+                return None;
+            }
+
+            record.normalized_address().map(|address| (record, address))
+        })
+    }
+
+    pub fn nearest(&self, coordinates: (f64, f64), within: f64) -> Option<(&FccRecord, f64)> {
+        let (row, column) = grid_cell(coordinates.0, coordinates.1);
+        let mut nearest: Option<(&FccRecord, f64)> = None;
+
+        for cell_row in row - 1..=row + 1 {
+            for cell_column in column - 1..=column + 1 {
+                for position in positions(&self.grid, &(cell_row, cell_column)) {
+                    let record = &self.records[position];
+                    let distance =
+                        great_circle_distance(coordinates, (record.latitude, record.longitude));
+
+                    if distance <= within && nearest.is_none_or(|(_, best)| distance < best) {
+                        nearest = Some((record, distance));
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+}
@@ -5,13 +5,27 @@ extern crate log;
 
 use dotenvy::dotenv;
 use lazy_static::lazy_static;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use std::env;
 
 pub mod analysis;
+pub mod carryover;
+pub mod catalog;
+pub mod diff;
 pub mod emerald;
 pub mod fcc;
+pub mod geocode;
+pub mod index;
+pub mod manifest;
+pub mod normalize;
+pub mod overrides;
+pub mod pipeline;
 pub mod reports;
 pub mod routes;
+pub mod schema;
+pub mod snapshot;
+pub mod store;
+pub mod subscriber;
 
 lazy_static! {
     pub static ref API_KEY: String = {
@@ -22,4 +36,52 @@ lazy_static! {
         dotenv().ok();
         env::var("API_URL").expect("API_URL must be set")
     };
+    // the product catalog, see catalog.rs
+    pub static ref CATALOG_PATH: String = {
+        dotenv().ok();
+        env::var("CATALOG_PATH").unwrap_or_else(|_| "catalog.toml".to_string())
+    };
+    // the bearer token the routes that change stored data require; unset,
+    // they're turned off
+    pub static ref ADMIN_TOKEN: Option<String> = {
+        dotenv().ok();
+        env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty())
+    };
+    // 64 hex characters; when set, subscriber snapshots are encrypted
+    pub static ref SNAPSHOT_KEY: Option<String> = {
+        dotenv().ok();
+        env::var("SNAPSHOT_KEY").ok().filter(|key| !key.is_empty())
+    };
+    // seconds a run may spend fetching from ChargeBee before it gives up
+    pub static ref CHARGEBEE_TIMEOUT: u64 = {
+        dotenv().ok();
+        env::var("CHARGEBEE_TIMEOUT")
+            .ok()
+            .and_then(|timeout| timeout.parse().ok())
+            .unwrap_or(1800)
+    };
+    // where service addresses are geocoded for the nearest tier; unset,
+    // that tier never links anything
+    pub static ref GEOCODER: Option<Box<dyn geocode::Geocoder>> = {
+        dotenv().ok();
+        env::var("GEOCODER_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .map(|url| Box::new(geocode::CensusGeocoder::new(url)) as Box<dyn geocode::Geocoder>)
+    };
+    // linking and summarization run on this pool; THREADS=0 or unset uses
+    // every core
+    pub static ref THREAD_POOL: ThreadPool = {
+        dotenv().ok();
+        ThreadPoolBuilder::new()
+            .num_threads(
+                env::var("THREADS")
+                    .ok()
+                    .and_then(|threads| threads.parse().ok())
+                    .unwrap_or(0),
+            )
+            .thread_name(|i| format!("analysis-{i}"))
+            .build()
+            .expect("unable to build the analysis thread pool")
+    };
 }
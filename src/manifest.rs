@@ -0,0 +1,95 @@
+use std::fs;
+use std::io;
+
+use glob::glob;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Complete,
+    Failed,
+}
+
+// Written to output/reports/manifest-<uuid>.json when a run starts and
+// rewritten as it progresses, so the record of what a run used survives
+// even if it fails part way through.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunManifest {
+    pub uuid: String,
+    pub status: RunStatus,
+    pub started_at: i64,
+    pub completed_at: Option<i64>,
+    pub fabric_release: Option<i64>,
+    pub fabric_version: Option<String>,
+    pub catalog_version: Option<String>,
+    pub subscriber_source: Option<String>,
+    pub snapshot: Option<String>,
+    pub as_of: Option<String>,
+    // false when the carryover tier was skipped, as it is for a replayed
+    // snapshot
+    pub carryover: bool,
+    pub error: Option<String>,
+}
+
+impl RunManifest {
+    pub fn new(uuid: &str) -> Self {
+        RunManifest {
+            uuid: uuid.to_string(),
+            status: RunStatus::Running,
+            started_at: chrono::Utc::now().timestamp(),
+            completed_at: None,
+            fabric_release: None,
+            fabric_version: None,
+            catalog_version: None,
+            subscriber_source: None,
+            snapshot: None,
+            as_of: None,
+            carryover: false,
+            error: None,
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(
+            format!("output/reports/manifest-{}.json", self.uuid),
+            serde_json::to_string_pretty(self)?,
+        )
+    }
+
+    pub fn load(uuid: &str) -> Option<RunManifest> {
+        // uuids come in from routes
+        if uuid.is_empty() || !uuid.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+
+        let manifest = fs::read_to_string(format!("output/reports/manifest-{uuid}.json")).ok()?;
+
+        serde_json::from_str(&manifest).ok()
+    }
+
+    // newest first; manifests that can't be read are skipped
+    pub fn list() -> Vec<RunManifest> {
+        let mut manifests: Vec<RunManifest> = glob("output/reports/manifest-*.json")
+            .expect("Failed to read glob pattern")
+            .filter_map(|path| fs::read_to_string(path.ok()?).ok())
+            .filter_map(|manifest| serde_json::from_str(&manifest).ok())
+            .collect();
+        manifests.sort_by_key(|manifest| std::cmp::Reverse(manifest.started_at));
+
+        manifests
+    }
+
+    pub fn complete(&mut self) {
+        self.status = RunStatus::Complete;
+        self.completed_at = Some(chrono::Utc::now().timestamp());
+    }
+
+    pub fn fail(&mut self, error: impl ToString) {
+        self.status = RunStatus::Failed;
+        self.completed_at = Some(chrono::Utc::now().timestamp());
+        self.error = Some(error.to_string());
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::index::digits;
+
+// Standardization follows USPS Publication 28 closely enough to line up
+// the ChargeBee service addresses with the Fabric's address_primary. Only
+// the pieces that matter for linking are handled: street suffixes,
+// directionals, secondary unit designators and spelled-out ordinal
+// street names.
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize)]
+pub struct Unit {
+    pub designator: String,
+    pub id: String,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.id.is_empty() {
+            write!(f, "{}", self.designator)
+        } else {
+            write!(f, "{} {}", self.designator, self.id)
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize)]
+pub struct NormalizedAddress {
+    pub number: String,
+    pub street: String,
+    pub unit: Option<Unit>,
+}
+
+impl NormalizedAddress {
+    // the number and street without any secondary unit, which is what the
+    // Fabric carries in address_primary
+    pub fn primary(&self) -> String {
+        format!("{} {}", self.number, self.street)
+    }
+
+    pub fn digits(&self) -> String {
+        digits(&self.primary())
+    }
+}
+
+impl fmt::Display for NormalizedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some(unit) => write!(f, "{} {} {}", self.number, self.street, unit),
+            None => write!(f, "{} {}", self.number, self.street),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.to_uppercase()
+        .replace(['.', ','], " ")
+        // "#4" and "# 4" should both come out as a designator and an id
+        .replace('#', " # ")
+        .split_whitespace()
+        .map(|token| token.to_string())
+        .collect()
+}
+
+pub fn normalize_city(text: &str) -> String {
+    tokenize(text).join(" ")
+}
+
+pub fn normalize_address(text: &str) -> Option<NormalizedAddress> {
+    let tokens = tokenize(text);
+    let (number, rest) = tokens.split_first()?;
+
+    // everything from the first unit designator onwards is the secondary
+    // address; a trailing designator with no id (e.g. "REAR") is kept as is
+    let (street, unit) = match (0..rest.len()).find(|&position| starts_unit(rest, position)) {
+        Some(position) if position > 0 => {
+            let designator = unit_designator(&rest[position]).unwrap_or_default();
+            let id = rest[position + 1..]
+                .iter()
+                .filter(|token| *token != "#")
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            (
+                &rest[..position],
+                Some(Unit {
+                    designator: designator.to_string(),
+                    id,
+                }),
+            )
+        }
+        _ => (rest, None),
+    };
+
+    if street.is_empty() {
+        return None;
+    }
+
+    Some(NormalizedAddress {
+        number: number.clone(),
+        street: normalize_street(street),
+        unit,
+    })
+}
+
+fn normalize_street(tokens: &[String]) -> String {
+    let last = tokens.len() - 1;
+
+    // a leading or trailing directional is only abbreviated when it isn't
+    // the street name itself, as in "NORTH ST" or "WEST"
+    let pre_directional =
+        tokens.len() > 2 || (tokens.len() == 2 && street_suffix(&tokens[last]).is_none());
+    let post_directional = tokens.len() > 2 && directional(&tokens[last]).is_some();
+    let suffix = if post_directional { last - 1 } else { last };
+
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(position, token)| {
+            let token = token.as_str();
+
+            if (position == 0 && pre_directional) || (position == last && post_directional) {
+                directional(token)
+            } else if position == suffix && position > 0 {
+                street_suffix(token)
+            } else {
+                None
+            }
+            .or_else(|| ordinal(token))
+            .unwrap_or(token)
+            .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn directional(token: &str) -> Option<&'static str> {
+    match token {
+        "N" | "NORTH" => Some("N"),
+        "S" | "SOUTH" => Some("S"),
+        "E" | "EAST" => Some("E"),
+        "W" | "WEST" => Some("W"),
+        "NE" | "NORTHEAST" => Some("NE"),
+        "NW" | "NORTHWEST" => Some("NW"),
+        "SE" | "SOUTHEAST" => Some("SE"),
+        "SW" | "SOUTHWEST" => Some("SW"),
+        _ => None,
+    }
+}
+
+fn street_suffix(token: &str) -> Option<&'static str> {
+    match token {
+        "ALLEY" | "ALLEE" | "ALLY" | "ALY" => Some("ALY"),
+        "AVENUE" | "AV" | "AVE" | "AVEN" | "AVENU" | "AVN" | "AVNUE" => Some("AVE"),
+        "BEND" | "BND" => Some("BND"),
+        "BOULEVARD" | "BLVD" | "BOUL" | "BOULV" => Some("BLVD"),
+        "CIRCLE" | "CIR" | "CIRC" | "CIRCL" | "CRCL" | "CRCLE" => Some("CIR"),
+        "COURT" | "CT" | "CRT" => Some("CT"),
+        "COVE" | "CV" => Some("CV"),
+        "CREEK" | "CRK" => Some("CRK"),
+        "CROSSING" | "CRSSNG" | "XING" => Some("XING"),
+        "DRIVE" | "DR" | "DRIV" | "DRV" => Some("DR"),
+        "EXPRESSWAY" | "EXP" | "EXPR" | "EXPRESS" | "EXPW" | "EXPY" => Some("EXPY"),
+        "FREEWAY" | "FREEWY" | "FRWAY" | "FRWY" | "FWY" => Some("FWY"),
+        "HIGHWAY" | "HIGHWY" | "HIWAY" | "HIWY" | "HWAY" | "HWY" => Some("HWY"),
+        "HILL" | "HL" => Some("HL"),
+        "LANE" | "LN" => Some("LN"),
+        "LOOP" | "LOOPS" => Some("LOOP"),
+        "PARKWAY" | "PARKWY" | "PKWAY" | "PKWY" | "PKY" => Some("PKWY"),
+        "PLACE" | "PL" => Some("PL"),
+        "PLAZA" | "PLZ" | "PLZA" => Some("PLZ"),
+        "POINT" | "PT" => Some("PT"),
+        "ROAD" | "RD" => Some("RD"),
+        "ROUTE" | "RTE" => Some("RTE"),
+        "SQUARE" | "SQ" | "SQR" | "SQRE" | "SQU" => Some("SQ"),
+        "STREET" | "ST" | "STR" | "STRT" => Some("ST"),
+        "TERRACE" | "TER" | "TERR" => Some("TER"),
+        "TRAIL" | "TRAILS" | "TRL" | "TRLS" => Some("TRL"),
+        "VIEW" | "VW" => Some("VW"),
+        "WAY" | "WY" => Some("WAY"),
+        _ => None,
+    }
+}
+
+fn unit_designator(token: &str) -> Option<&'static str> {
+    match token {
+        "APARTMENT" | "APT" => Some("APT"),
+        "BUILDING" | "BLDG" => Some("BLDG"),
+        "FLOOR" | "FL" => Some("FL"),
+        "LOT" => Some("LOT"),
+        "REAR" => Some("REAR"),
+        "ROOM" | "RM" => Some("RM"),
+        "SPACE" | "SPC" => Some("SPC"),
+        "SUITE" | "STE" => Some("STE"),
+        "TRAILER" | "TRLR" => Some("TRLR"),
+        "UNIT" => Some("UNIT"),
+        "#" => Some("#"),
+        _ => None,
+    }
+}
+
+// LOT, FL, REAR, RM and SPC are also words in street names ("5 LOT RD",
+// "100 OLD REAR RD"), so they only start the secondary address right after
+// a street suffix or when an id follows them
+fn starts_unit(tokens: &[String], position: usize) -> bool {
+    match unit_designator(&tokens[position]) {
+        Some("LOT" | "FL" | "REAR" | "RM" | "SPC") => {
+            (position > 0 && street_suffix(&tokens[position - 1]).is_some())
+                || tokens.get(position + 1).is_some_and(|id| is_unit_id(id))
+        }
+        Some(_) => true,
+        None => false,
+    }
+}
+
+fn is_unit_id(token: &str) -> bool {
+    token.len() == 1 || token.chars().any(|c| c.is_ascii_digit())
+}
+
+fn ordinal(token: &str) -> Option<&'static str> {
+    match token {
+        "FIRST" => Some("1ST"),
+        "SECOND" => Some("2ND"),
+        "THIRD" => Some("3RD"),
+        "FOURTH" => Some("4TH"),
+        "FIFTH" => Some("5TH"),
+        "SIXTH" => Some("6TH"),
+        "SEVENTH" => Some("7TH"),
+        "EIGHTH" => Some("8TH"),
+        "NINTH" => Some("9TH"),
+        "TENTH" => Some("10TH"),
+        "ELEVENTH" => Some("11TH"),
+        "TWELFTH" => Some("12TH"),
+        "THIRTEENTH" => Some("13TH"),
+        "FOURTEENTH" => Some("14TH"),
+        "FIFTEENTH" => Some("15TH"),
+        "SIXTEENTH" => Some("16TH"),
+        "SEVENTEENTH" => Some("17TH"),
+        "EIGHTEENTH" => Some("18TH"),
+        "NINETEENTH" => Some("19TH"),
+        "TWENTIETH" => Some("20TH"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn street(text: &str) -> String {
+        normalize_address(text).unwrap().street
+    }
+
+    #[test]
+    fn abbreviates_directionals() {
+        assert_eq!(street("123 North Main Street"), "N MAIN ST");
+        assert_eq!(street("45 Main St Southwest"), "MAIN ST SW");
+        assert_eq!(street("9 E. Broadway"), "E BROADWAY");
+    }
+
+    #[test]
+    fn keeps_directionals_that_are_the_street_name() {
+        assert_eq!(street("12 North St"), "NORTH ST");
+        assert_eq!(street("12 West"), "WEST");
+    }
+
+    #[test]
+    fn abbreviates_street_suffixes() {
+        assert_eq!(street("100 Oak Avenue"), "OAK AVE");
+        assert_eq!(street("100 Oak Boulevard"), "OAK BLVD");
+        assert_eq!(street("100 Lakeview Parkway"), "LAKEVIEW PKWY");
+        // only the last word is a suffix
+        assert_eq!(street("7 Avenue B"), "AVENUE B");
+    }
+
+    #[test]
+    fn splits_off_units() {
+        let address = normalize_address("123 Main St., Apartment 4B").unwrap();
+        assert_eq!(address.number, "123");
+        assert_eq!(address.street, "MAIN ST");
+        assert_eq!(
+            address.unit,
+            Some(Unit {
+                designator: "APT".to_string(),
+                id: "4B".to_string(),
+            })
+        );
+        assert_eq!(address.primary(), "123 MAIN ST");
+        assert_eq!(address.to_string(), "123 MAIN ST APT 4B");
+    }
+
+    #[test]
+    fn reads_number_signs_as_units() {
+        for text in ["123 Main St #4", "123 Main St # 4"] {
+            let unit = normalize_address(text).unwrap().unit.unwrap();
+            assert_eq!(unit.designator, "#");
+            assert_eq!(unit.id, "4");
+        }
+    }
+
+    #[test]
+    fn keeps_units_without_an_id() {
+        let address = normalize_address("5 Elm Street Rear").unwrap();
+        assert_eq!(address.street, "ELM ST");
+        assert_eq!(address.to_string(), "5 ELM ST REAR");
+    }
+
+    #[test]
+    fn keeps_designator_words_in_street_names() {
+        for (text, expected) in [
+            ("12 Fl Hwy 9", "12 FL HWY 9"),
+            ("5 Lot Rd", "5 LOT RD"),
+            ("100 Old Rear Rd", "100 OLD REAR RD"),
+        ] {
+            let address = normalize_address(text).unwrap();
+            assert_eq!(address.unit, None);
+            assert_eq!(address.to_string(), expected);
+        }
+
+        // but not once an id follows them
+        let unit = normalize_address("8 Pine Hwy Lot 12")
+            .unwrap()
+            .unit
+            .unwrap();
+        assert_eq!(unit.to_string(), "LOT 12");
+    }
+
+    #[test]
+    fn numbers_spelled_out_ordinals() {
+        assert_eq!(street("200 West Third Street"), "W 3RD ST");
+        assert_eq!(street("1 First Ave"), "1ST AVE");
+        assert_eq!(street("18 Twentieth"), "20TH");
+    }
+
+    #[test]
+    fn needs_a_number_and_a_street() {
+        assert_eq!(normalize_address(""), None);
+        assert_eq!(normalize_address("123"), None);
+    }
+}
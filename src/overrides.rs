@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::subscriber::Subscriber;
+
+const OVERRIDES_PATH: &str = "output/overrides.json";
+
+lazy_static! {
+    // the routes do a read-modify-write of the whole file, so they need to
+    // take turns
+    static ref OVERRIDES_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OverrideKind {
+    Customer,
+    Subscription,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Override {
+    pub kind: OverrideKind,
+    pub id: String,
+    pub location_id: String,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Overrides {
+    entries: Vec<Override>,
+}
+
+impl Overrides {
+    pub fn load() -> io::Result<Self> {
+        match fs::read_to_string(OVERRIDES_PATH) {
+            Ok(contents) => Ok(Overrides {
+                entries: serde_json::from_str(&contents)?,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Overrides::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let temporary = format!("{OVERRIDES_PATH}.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&self.entries)?)?;
+        fs::rename(temporary, OVERRIDES_PATH)
+    }
+
+    pub fn entries(&self) -> &[Override] {
+        &self.entries
+    }
+
+    // adding an override for an id that already has one replaces it
+    pub fn add(entry: Override) -> io::Result<()> {
+        let _guard = OVERRIDES_LOCK.lock().unwrap();
+        let mut overrides = Overrides::load()?;
+
+        overrides
+            .entries
+            .retain(|existing| existing.kind != entry.kind || existing.id != entry.id);
+        overrides.entries.push(entry);
+        overrides.save()
+    }
+
+    pub fn remove(kind: OverrideKind, id: &str) -> io::Result<bool> {
+        let _guard = OVERRIDES_LOCK.lock().unwrap();
+        let mut overrides = Overrides::load()?;
+        let before = overrides.entries.len();
+
+        overrides
+            .entries
+            .retain(|existing| existing.kind != kind || existing.id != id);
+
+        if overrides.entries.len() == before {
+            return Ok(false);
+        }
+
+        overrides.save().map(|_| true)
+    }
+
+    // a subscription override is more specific than a customer override,
+    // so it wins when both exist
+    pub fn lookup(&self, subscriber: &Subscriber) -> Option<&Override> {
+        let find = |kind: OverrideKind, id: &Option<String>| {
+            id.as_ref().and_then(|id| {
+                self.entries
+                    .iter()
+                    .find(|entry| entry.kind == kind && &entry.id == id)
+            })
+        };
+
+        find(OverrideKind::Subscription, &subscriber.subscription_id)
+            .or_else(|| find(OverrideKind::Customer, &subscriber.customer_id))
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use chrono::NaiveDate;
+
+use crate::analysis::{link, summarize_locations, summarize_tracts, summarize_units, MatchConfig};
+use crate::carryover::PriorLinks;
+use crate::catalog::{CatalogError, ProductCatalog};
+use crate::index::FccIndex;
+use crate::manifest::RunManifest;
+use crate::overrides::Overrides;
+use crate::reports::{
+    broadband_availability_report, broadband_subscription_report, links_report, unit_count_report,
+    unmapped_plans_report, unmatched_report, voice_subscription_report, warnings_report,
+};
+use crate::store::{FabricStore, StoreError};
+use crate::subscriber::{cutoff, SourceError, SubscriberSource};
+
+#[derive(Debug)]
+pub enum RunError {
+    Store(StoreError),
+    Source(SourceError),
+    Report(io::Error),
+    Catalog(CatalogError),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Store(e) => write!(f, "{e}"),
+            RunError::Source(e) => write!(f, "{e}"),
+            RunError::Report(e) => write!(f, "unable to write the reports: {e}"),
+            RunError::Catalog(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunError::Store(e) => Some(e),
+            RunError::Source(e) => Some(e),
+            RunError::Report(e) => Some(e),
+            RunError::Catalog(e) => Some(e),
+        }
+    }
+}
+
+impl From<StoreError> for RunError {
+    fn from(e: StoreError) -> Self {
+        RunError::Store(e)
+    }
+}
+
+impl From<CatalogError> for RunError {
+    fn from(e: CatalogError) -> Self {
+        RunError::Catalog(e)
+    }
+}
+
+impl From<SourceError> for RunError {
+    fn from(e: SourceError) -> Self {
+        RunError::Source(e)
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(e: io::Error) -> Self {
+        RunError::Report(e)
+    }
+}
+
+impl From<csv::Error> for RunError {
+    fn from(e: csv::Error) -> Self {
+        RunError::Report(e.into())
+    }
+}
+
+// Either a fresh upload, which is stored as a new release before the run
+// uses it, or a release stored by an earlier upload. An upload without a
+// release_date is dated today, or keeps its date if it's already stored.
+pub enum FabricSource {
+    Upload {
+        filename: String,
+        release_date: Option<String>,
+    },
+    Release(i64),
+}
+
+// Everything a /focus submission kicks off: store or load the Fabric, link
+// it to the subscribers from the billing source and write the reports. The
+// manifest records how the run went whether it succeeds or not.
+//
+// With as_of set only subscribers that were live at the end of that day
+// are linked, whatever the source.
+pub fn run(
+    uuid: String,
+    source: FabricSource,
+    subscribers: Box<dyn SubscriberSource>,
+    as_of: Option<NaiveDate>,
+) {
+    let mut manifest = RunManifest::new(&uuid);
+    manifest.subscriber_source = Some(subscribers.name().to_string());
+    manifest.as_of = as_of.map(|as_of| as_of.format("%Y-%m-%d").to_string());
+    manifest.save().ok();
+
+    match execute(&uuid, &source, subscribers.as_ref(), as_of, &mut manifest) {
+        Ok(()) => manifest.complete(),
+        Err(e) => {
+            log::error!("{e}");
+            manifest.fail(e);
+        }
+    }
+
+    if let Err(e) = manifest.save() {
+        log::error!("unable to save the manifest for {uuid}: {e}");
+    }
+
+    log::debug!("THREAD COMPLETE");
+}
+
+fn execute(
+    uuid: &String,
+    source: &FabricSource,
+    subscriber_source: &dyn SubscriberSource,
+    as_of: Option<NaiveDate>,
+    manifest: &mut RunManifest,
+) -> Result<(), RunError> {
+    let catalog = ProductCatalog::load()?;
+    manifest.catalog_version = Some(catalog.version().to_string());
+
+    if let Err(e) = catalog.archive() {
+        log::error!(
+            "unable to archive product catalog {}: {e}",
+            catalog.version()
+        );
+    }
+
+    let mut store = FabricStore::open()?;
+
+    let (release, ingest_report) = match source {
+        FabricSource::Upload {
+            filename,
+            release_date,
+        } => store.import(filename, release_date.as_deref())?,
+        FabricSource::Release(id) => {
+            let release = store.release(*id)?.ok_or(StoreError::NotFound(*id))?;
+            let ingest_report = store.ingest_report(*id)?;
+            (release, ingest_report)
+        }
+    };
+    manifest.fabric_release = Some(release.id);
+    manifest.fabric_version = Some(release.fabric_version.clone());
+    manifest.save().ok();
+
+    let index = FccIndex::load(&store, release.id)?;
+    log::debug!(
+        "LOADED {} LOCATIONS FROM FABRIC RELEASE {}",
+        index.len(),
+        release.id
+    );
+
+    write_json(format!("output/reports/ingest-{uuid}.json"), &ingest_report)?;
+
+    let overrides = Overrides::load().unwrap_or_else(|e| {
+        log::error!("unable to load overrides: {e}");
+        Overrides::default()
+    });
+
+    // a replayed snapshot is linked without links from other runs, so it
+    // comes out the same however many runs there have been since
+    let source_name = subscriber_source.name();
+    let carryover = !subscriber_source.replays_snapshot();
+    let prior = if carryover {
+        PriorLinks::load(&store, source_name).unwrap_or_else(|e| {
+            log::error!("unable to load prior links: {e}");
+            PriorLinks::default()
+        })
+    } else {
+        PriorLinks::default()
+    };
+    manifest.carryover = carryover;
+    log::debug!("{} PRIOR LINKS", prior.len());
+
+    let mut subscribers = subscriber_source.subscribers()?;
+    manifest.snapshot = subscriber_source.snapshot_id();
+    manifest.save().ok();
+
+    if let Some(as_of) = as_of {
+        let cutoff = cutoff(as_of);
+        subscribers.retain(|subscriber| subscriber.subscribed_on(cutoff));
+        log::debug!("{} SUBSCRIBERS AS OF {as_of}", subscribers.len());
+    }
+
+    let unmapped = catalog.unmapped_plans(&subscribers);
+    if !unmapped.is_empty() {
+        log::warn!("{} PLAN IDS AREN'T IN THE CATALOG", unmapped.len());
+    }
+    unmapped_plans_report(uuid, &unmapped)?;
+
+    let linkage = link(
+        &index,
+        subscribers,
+        &overrides,
+        &prior,
+        &MatchConfig::from_env(),
+    );
+    let linked_records = linkage.linked;
+
+    // only a run of today's subscribers against the newest release is
+    // remembered, so rerunning an older release or filing period can't
+    // pull subscribers back onto the locations they had then
+    let latest = store.releases()?.first().map(|latest| latest.id);
+    if carryover && as_of.is_none() && latest == Some(release.id) {
+        if let Err(e) = PriorLinks::save(&mut store, source_name, release.id, &linked_records) {
+            log::error!("unable to save links for the next run: {e}");
+        }
+    }
+
+    links_report("linked", uuid, &linked_records)?;
+    links_report("review", uuid, &linkage.review)?;
+    unit_count_report(uuid, &summarize_units(&linked_records))?;
+    unmatched_report(uuid, &linkage.unmatched)?;
+    warnings_report(uuid, &linkage.warnings)?;
+
+    let tract_summarization = summarize_tracts(linked_records.clone(), &catalog);
+    let broadband_statistic = broadband_subscription_report(uuid, &tract_summarization)?;
+    let voip_statistic = voice_subscription_report(uuid, &tract_summarization)?;
+
+    let location_summarization = summarize_locations(linked_records.clone(), &catalog);
+    broadband_availability_report(uuid, &location_summarization)?;
+
+    //log::debug!("ENTRIES: {}", linked_records.len());
+    //log::debug!("TRACT SUMMARIZATION\n{tract_summarization:#?}");
+    //log::debug!("LOCATION SUMMARIZATION\n{location_summarization:#?}");
+    log::debug!("BROADBAND STATISTIC\n{broadband_statistic:#?}");
+    log::debug!("VOIP STATISTIC\n{voip_statistic:#?}");
+
+    write_json(
+        format!("output/reports/broadband_statistics-{uuid}.json"),
+        &broadband_statistic,
+    )?;
+    write_json(
+        format!("output/reports/voip_statistics-{uuid}.json"),
+        &voip_statistic,
+    )?;
+
+    Ok(())
+}
+
+// the JSON files written alongside the CSV reports
+fn write_json(path: String, value: &impl serde::Serialize) -> io::Result<()> {
+    fs::write(path, serde_json::to_vec(value)?)
+}
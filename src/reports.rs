@@ -1,23 +1,72 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    analysis::{LocationSummationKey, Summation, TractSummationKey},
-    emerald::ProductType,
+    analysis::{
+        Link, LocationSummationKey, Summation, TractSummationKey, UnitOccupancy, Unmatched,
+        UnmatchedReason, Warning,
+    },
+    catalog::UnmappedPlan,
+    diff::{LocationChange, SubscriberChange},
+    emerald::{ProductType, ServiceMedium},
     routes::focus::{BroadbandStatistic, VoipStatistic},
 };
 
+// Rows are written in a stable order so consecutive filings can be diffed:
+// by tract, then technology code, then download and upload speed for the
+// subscription reports, and by location_id (then technology and speeds)
+// for availability. The rest of the product type breaks ties, since two
+// plans can share committed speeds but not available ones.
+type SpeedOrder = (u8, u8, u16, u16, u16, u16, ServiceMedium);
+
+fn speed_order(product_type: &ProductType) -> SpeedOrder {
+    match product_type {
+        ProductType::Internet(service_profile) => (
+            0,
+            service_profile.technology_code,
+            service_profile.committed_bandwidth_down,
+            service_profile.committed_bandwidth_up,
+            service_profile.available_bandwidth_down,
+            service_profile.available_bandwidth_up,
+            service_profile.medium.clone(),
+        ),
+        ProductType::Voip => (1, 0, 0, 0, 0, 0, ServiceMedium::Fiber),
+        ProductType::Fax => (2, 0, 0, 0, 0, 0, ServiceMedium::Fiber),
+        ProductType::Admin => (3, 0, 0, 0, 0, 0, ServiceMedium::Fiber),
+    }
+}
+
+fn sorted_tracts(
+    summarization: &HashMap<TractSummationKey, Summation>,
+) -> Vec<(&TractSummationKey, &Summation)> {
+    let mut entries: Vec<(&TractSummationKey, &Summation)> = summarization.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| {
+        (&a.tract_id, speed_order(&a.product_type))
+            .cmp(&(&b.tract_id, speed_order(&b.product_type)))
+    });
+
+    entries
+}
+
+fn sorted_locations(summarization: &HashSet<LocationSummationKey>) -> Vec<&LocationSummationKey> {
+    let mut entries: Vec<&LocationSummationKey> = summarization.iter().collect();
+    entries.sort_by(|a, b| {
+        (&a.location_id, speed_order(&a.product_type))
+            .cmp(&(&b.location_id, speed_order(&b.product_type)))
+    });
+
+    entries
+}
+
 pub fn broadband_subscription_report(
     uuid: &str,
     summarization: &HashMap<TractSummationKey, Summation>,
-) -> BroadbandStatistic {
+) -> csv::Result<BroadbandStatistic> {
     let mut statistic = BroadbandStatistic::default();
 
     let now = chrono::Utc::now().timestamp();
-    let mut wtr = csv::WriterBuilder::new()
-        .from_path(format!(
-            "output/reports/broadband_subscription-{now}-{uuid}.csv"
-        ))
-        .unwrap();
+    let mut wtr = csv::WriterBuilder::new().from_path(format!(
+        "output/reports/broadband_subscription-{now}-{uuid}.csv"
+    ))?;
 
     wtr.write_record([
         "tract",
@@ -29,7 +78,7 @@ pub fn broadband_subscription_report(
     ])
     .ok();
 
-    for (key, summation) in summarization {
+    for (key, summation) in sorted_tracts(summarization) {
         statistic = statistic + ((*key).clone(), (*summation).clone()).into();
         if let ProductType::Internet(service_profile) = key.product_type.clone() {
             wtr.write_record(&[
@@ -44,22 +93,20 @@ pub fn broadband_subscription_report(
         }
     }
 
-    wtr.flush().ok();
+    wtr.flush()?;
 
-    statistic
+    Ok(statistic)
 }
 
 pub fn voice_subscription_report(
     uuid: &String,
     summarization: &HashMap<TractSummationKey, Summation>,
-) -> VoipStatistic {
+) -> csv::Result<VoipStatistic> {
     let mut statistic = VoipStatistic::default();
     let now = chrono::Utc::now().timestamp();
-    let mut wtr = csv::WriterBuilder::new()
-        .from_path(format!(
-            "output/reports/voice_subscription-{now}-{uuid}.csv"
-        ))
-        .unwrap();
+    let mut wtr = csv::WriterBuilder::new().from_path(format!(
+        "output/reports/voice_subscription-{now}-{uuid}.csv"
+    ))?;
 
     wtr.write_record([
         "tract",
@@ -69,7 +116,7 @@ pub fn voice_subscription_report(
     ])
     .ok();
 
-    for (key, summation) in summarization {
+    for (key, summation) in sorted_tracts(summarization) {
         statistic = statistic + ((*key).clone(), (*summation).clone()).into();
         if let ProductType::Voip = key.product_type {
             wtr.write_record(&[
@@ -82,18 +129,19 @@ pub fn voice_subscription_report(
         }
     }
 
-    wtr.flush().ok();
+    wtr.flush()?;
 
-    statistic
+    Ok(statistic)
 }
 
-pub fn broadband_availability_report(uuid: &String, summarization: &HashSet<LocationSummationKey>) {
+pub fn broadband_availability_report(
+    uuid: &String,
+    summarization: &HashSet<LocationSummationKey>,
+) -> csv::Result<()> {
     let now = chrono::Utc::now().timestamp();
-    let mut wtr = csv::WriterBuilder::new()
-        .from_path(format!(
-            "output/reports/broadband_availability-{now}-{uuid}.csv"
-        ))
-        .unwrap();
+    let mut wtr = csv::WriterBuilder::new().from_path(format!(
+        "output/reports/broadband_availability-{now}-{uuid}.csv"
+    ))?;
 
     wtr.write_record([
         "provider_id",
@@ -107,7 +155,7 @@ pub fn broadband_availability_report(uuid: &String, summarization: &HashSet<Loca
     ])
     .ok();
 
-    for key in summarization {
+    for key in sorted_locations(summarization) {
         if let ProductType::Internet(service_profile) = key.product_type.clone() {
             wtr.write_record(&[
                 "410035".to_string(),
@@ -123,5 +171,255 @@ pub fn broadband_availability_report(uuid: &String, summarization: &HashSet<Loca
         }
     }
 
-    wtr.flush().ok();
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn unmatched_report(uuid: &String, unmatched: &[Unmatched]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr = csv::WriterBuilder::new()
+        .from_path(format!("output/reports/unmatched-{now}-{uuid}.csv"))?;
+
+    wtr.write_record([
+        "subscription_id",
+        "customer_id",
+        "plan_id",
+        "service_address",
+        "service_city_st_zip",
+        "normalized_address",
+        "reason",
+        "closest_score",
+        "closest_location_id",
+        "closest_address",
+    ])
+    .ok();
+
+    for entry in unmatched {
+        let subscriber = &entry.subscriber;
+        let (closest_score, closest_location_id, closest_address) = match &entry.reason {
+            UnmatchedReason::BelowThreshold { score, candidate } => (
+                score.to_string(),
+                candidate.location_id.clone(),
+                candidate
+                    .normalized_address()
+                    .map(|address| address.to_string())
+                    .unwrap_or_default(),
+            ),
+            _ => (String::new(), String::new(), String::new()),
+        };
+
+        wtr.write_record(&[
+            subscriber.subscription_id.clone().unwrap_or_default(),
+            subscriber.customer_id.clone().unwrap_or_default(),
+            subscriber.plan_id.clone().unwrap_or_default(),
+            subscriber.service_address.clone().unwrap_or_default(),
+            subscriber.service_city_st_zip.clone().unwrap_or_default(),
+            subscriber
+                .normalized_service_address()
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+            entry.reason.to_string(),
+            closest_score,
+            closest_location_id,
+            closest_address,
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn warnings_report(uuid: &String, warnings: &[Warning]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr =
+        csv::WriterBuilder::new().from_path(format!("output/reports/warnings-{now}-{uuid}.csv"))?;
+
+    wtr.write_record(["subscription_id", "customer_id", "warning"])
+        .ok();
+
+    for warning in warnings {
+        wtr.write_record(&[
+            warning.subscription_id.clone().unwrap_or_default(),
+            warning.customer_id.clone().unwrap_or_default(),
+            warning.message.clone(),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn unmapped_plans_report(uuid: &String, unmapped: &[UnmappedPlan]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr = csv::WriterBuilder::new()
+        .from_path(format!("output/reports/unmapped_plans-{now}-{uuid}.csv"))?;
+
+    wtr.write_record(["plan_id", "normalized_plan_id", "subscriptions"])
+        .ok();
+
+    for plan in unmapped {
+        wtr.write_record(&[
+            plan.plan_id.clone(),
+            plan.normalized.clone(),
+            plan.subscriptions.to_string(),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+// used for both the accepted links and the borderline ones held back for
+// review; the name is the report type at the front of the file name
+pub fn links_report(name: &str, uuid: &String, links: &[Link]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr =
+        csv::WriterBuilder::new().from_path(format!("output/reports/{name}-{now}-{uuid}.csv"))?;
+
+    wtr.write_record([
+        "subscription_id",
+        "customer_id",
+        "plan_id",
+        "normalized_address",
+        "location_id",
+        "fabric_address",
+        "tier",
+        "street_ratio",
+        "city_match",
+        "house_number_match",
+        "digits_match",
+        "unit_match",
+    ])
+    .ok();
+
+    for link in links {
+        wtr.write_record(&[
+            link.subscriber.subscription_id.clone().unwrap_or_default(),
+            link.subscriber.customer_id.clone().unwrap_or_default(),
+            link.subscriber.plan_id.clone().unwrap_or_default(),
+            link.subscriber
+                .normalized_service_address()
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+            link.fcc.location_id.clone(),
+            link.fcc
+                .normalized_address()
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+            link.score.tier.to_string(),
+            link.score.street_ratio.to_string(),
+            link.score.city_match.to_string(),
+            link.score.house_number_match.to_string(),
+            link.score.digits_match.to_string(),
+            link.score
+                .unit_match
+                .map(|unit_match| unit_match.to_string())
+                .unwrap_or_default(),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn unit_count_report(uuid: &String, occupancy: &[UnitOccupancy]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr = csv::WriterBuilder::new()
+        .from_path(format!("output/reports/unit_count-{now}-{uuid}.csv"))?;
+
+    wtr.write_record([
+        "location_id",
+        "fabric_address",
+        "unit_count",
+        "subscribers",
+        "units",
+    ])
+    .ok();
+
+    for location in occupancy.iter().filter(|x| x.exceeds_unit_count()) {
+        wtr.write_record(&[
+            location.fcc.location_id.clone(),
+            location.fcc.address_primary.clone().unwrap_or_default(),
+            location.fcc.unit_count.to_string(),
+            location.subscribers.to_string(),
+            location.units.join("; "),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn fabric_diff_report(uuid: &str, changes: &[LocationChange]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr = csv::WriterBuilder::new()
+        .from_path(format!("output/reports/fabric_diff-{now}-{uuid}.csv"))?;
+
+    wtr.write_record([
+        "location_id",
+        "change",
+        "state",
+        "county_geoid",
+        "from",
+        "to",
+    ])
+    .ok();
+
+    for change in changes {
+        wtr.write_record(&[
+            change.location_id.clone(),
+            change.kind.to_string(),
+            change.state.clone(),
+            change.county_geoid.clone(),
+            change.from.clone(),
+            change.to.clone(),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+pub fn subscriber_changes_report(uuid: &str, changes: &[SubscriberChange]) -> csv::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let mut wtr = csv::WriterBuilder::new().from_path(format!(
+        "output/reports/fabric_diff_subscribers-{now}-{uuid}.csv"
+    ))?;
+
+    wtr.write_record([
+        "subscription_id",
+        "customer_id",
+        "change",
+        "from_location_id",
+        "to_location_id",
+    ])
+    .ok();
+
+    for change in changes {
+        wtr.write_record(&[
+            change.subscription_id.clone(),
+            change.customer_id.clone().unwrap_or_default(),
+            change.kind.to_string(),
+            change.from_location_id.clone(),
+            change.to_location_id.clone().unwrap_or_default(),
+        ])
+        .ok();
+    }
+
+    wtr.flush()?;
+
+    Ok(())
 }
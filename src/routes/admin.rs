@@ -0,0 +1,32 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+
+// Routes that change what's stored take this guard, so they need
+// `Authorization: Bearer <ADMIN_TOKEN>`. Without ADMIN_TOKEN set there's
+// no token to give, so they're refused outright.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let Some(token) = crate::ADMIN_TOKEN.as_deref() else {
+            return Outcome::Error((Status::Forbidden, ()));
+        };
+
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        // compared by digest so the time taken says nothing useful about the token
+        match given {
+            Some(given) if Sha256::digest(given) == Sha256::digest(token) => {
+                Outcome::Success(Admin)
+            }
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, put};
+use serde::Serialize;
+
+use crate::catalog::{CatalogError, Plan, PlanIdRules, ProductCatalog};
+use crate::routes::admin::Admin;
+
+// the catalog with its version, which the file itself doesn't carry
+#[derive(Serialize)]
+pub struct ProductCatalogView {
+    version: String,
+    plan_ids: PlanIdRules,
+    plans: BTreeMap<String, Plan>,
+}
+
+impl From<ProductCatalog> for ProductCatalogView {
+    fn from(catalog: ProductCatalog) -> Self {
+        ProductCatalogView {
+            version: catalog.version().to_string(),
+            plan_ids: catalog.plan_id_rules().clone(),
+            plans: catalog.plans().clone(),
+        }
+    }
+}
+
+fn status(e: CatalogError) -> (Status, String) {
+    log::error!("{e}");
+
+    match e {
+        CatalogError::Invalid(_) => (Status::UnprocessableEntity, e.to_string()),
+        _ => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/catalog")]
+pub async fn product_catalog() -> Result<Json<ProductCatalogView>, (Status, String)> {
+    ProductCatalog::load()
+        .map(|catalog| Json(catalog.into()))
+        .map_err(status)
+}
+
+#[put("/catalog/<plan_id>", format = "json", data = "<plan>")]
+pub async fn set_catalog_plan(
+    _admin: Admin,
+    plan_id: &str,
+    plan: Json<Plan>,
+) -> Result<Json<ProductCatalogView>, (Status, String)> {
+    ProductCatalog::set(plan_id, plan.into_inner())
+        .map(|catalog| Json(catalog.into()))
+        .map_err(status)
+}
+
+#[delete("/catalog/<plan_id>")]
+pub async fn delete_catalog_plan(_admin: Admin, plan_id: &str) -> Result<Status, (Status, String)> {
+    match ProductCatalog::remove(plan_id).map_err(status)? {
+        Some(_) => Ok(Status::NoContent),
+        None => Ok(Status::NotFound),
+    }
+}
@@ -0,0 +1,66 @@
+use std::thread;
+
+use rocket::{delete, get, http::Status, post, serde::json::Json};
+
+use crate::diff::{diff, DiffRequest};
+use crate::routes::admin::Admin;
+use crate::store::{FabricRelease, FabricReleaseDetail, FabricStore, StoreError};
+
+fn internal_error(e: StoreError) -> Status {
+    log::error!("{e}");
+    Status::InternalServerError
+}
+
+#[get("/fabric")]
+pub async fn fabric_release_list() -> Result<Json<Vec<FabricRelease>>, Status> {
+    FabricStore::open()
+        .and_then(|store| store.releases())
+        .map(Json)
+        .map_err(internal_error)
+}
+
+#[get("/fabric/<id>")]
+pub async fn fabric_release(id: i64) -> Result<Json<FabricReleaseDetail>, Status> {
+    match FabricStore::open().and_then(|store| store.detail(id)) {
+        Ok(Some(detail)) => Ok(Json(detail)),
+        Ok(None) => Err(Status::NotFound),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+#[delete("/fabric/<id>")]
+pub async fn delete_fabric_release(id: i64, _admin: Admin) -> Status {
+    match FabricStore::open().and_then(|mut store| store.delete_release(id)) {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(e) => internal_error(e),
+    }
+}
+
+// The diff runs in the background like a /focus run; the uuid it returns
+// names the fabric_diff reports it writes.
+#[post("/fabric/diff", format = "json", data = "<request>")]
+pub async fn diff_fabric_releases(
+    request: Json<DiffRequest>,
+    _admin: Admin,
+) -> Result<Json<String>, Status> {
+    let request = request.into_inner();
+    let store = FabricStore::open().map_err(internal_error)?;
+
+    for id in [request.from, request.to] {
+        if store.release(id).map_err(internal_error)?.is_none() {
+            return Err(Status::NotFound);
+        }
+    }
+
+    let uuid = uuid::Uuid::new_v4().to_string().replace('-', "");
+    let diff_uuid = uuid.clone();
+
+    thread::spawn(move || {
+        if let Err(e) = diff(&diff_uuid, &request) {
+            log::error!("{e}");
+        }
+    });
+
+    Ok(Json(uuid))
+}
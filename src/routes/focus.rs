@@ -1,23 +1,96 @@
+use std::collections::HashMap;
 use std::ops::Add;
-use std::thread;
-use std::io::prelude::*;
-use std::fs::File;
+use std::sync::{Arc, Mutex};
 
 use rocket::form::Form;
 use rocket::fs::TempFile;
-use rocket::post;
+use rocket::http::Status;
 use rocket::response::Redirect;
+use rocket::tokio::runtime::Handle;
+use rocket::tokio::task;
+use rocket::{delete, post, State};
 use serde::{Serialize, Deserialize};
+use tokio_util::sync::CancellationToken;
 
-use crate::analysis::{link, summarize_tracts, summarize_locations, TractSummationKey, Summation};
-use crate::fcc::process_fcc_data;
-use crate::emerald::{retrieve_subscriptions, ProductType, ServiceMedium};
-use crate::reports::{broadband_subscription_report, voice_subscription_report, broadband_availability_report};
+use crate::analysis::{Summation, TractSummationKey};
+use crate::emerald::{ChargeBeeSource, ProductType, ServiceMedium};
+use crate::manifest::RunManifest;
+use crate::pipeline::{run, FabricSource};
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotSource};
+use crate::subscriber::{
+    ColumnMapping, CsvSource, MergedSource, SourceError, Subscriber, SubscriberSource,
+};
 
 #[derive(FromForm)]
 pub struct Upload<'r> {
     _save: bool,
-    file: TempFile<'r>,
+    file: Option<TempFile<'r>>,
+    // a stored release to run against instead of uploading a file
+    release: Option<i64>,
+    // YYYY-MM-DD the uploaded Fabric was published
+    release_date: Option<String>,
+    // a subscriber CSV to link instead of, or with merge as well as, the
+    // ChargeBee subscriptions
+    subscribers: Option<TempFile<'r>>,
+    // JSON object naming the CSV column for each Subscriber field
+    subscriber_mapping: Option<String>,
+    merge: bool,
+    // YYYY-MM-DD to file as of, e.g. June 30 or December 31; defaults to
+    // the subscribers active now
+    as_of: Option<String>,
+    // a saved ChargeBee snapshot (GET /snapshots) to re-run against in
+    // place of fetching the subscriptions again
+    snapshot: Option<String>,
+}
+
+// The cancellation token of each run still in progress, by uuid, so a run
+// can be stopped while it's fetching from ChargeBee.
+#[derive(Default, Clone)]
+pub struct RunningRetrievals(Arc<Mutex<HashMap<String, CancellationToken>>>);
+
+impl RunningRetrievals {
+    fn insert(&self, uuid: &str, cancel: CancellationToken) {
+        self.0.lock().unwrap().insert(uuid.to_string(), cancel);
+    }
+
+    fn remove(&self, uuid: &str) {
+        self.0.lock().unwrap().remove(uuid);
+    }
+
+    fn cancel(&self, uuid: &str) -> bool {
+        match self.0.lock().unwrap().get(uuid) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// A run's ChargeBee fetch, which forgets the run's cancellation token as
+// soon as it's over so a later cancel isn't accepted and then ignored.
+struct Retrieving {
+    uuid: String,
+    source: ChargeBeeSource,
+    running: RunningRetrievals,
+}
+
+impl SubscriberSource for Retrieving {
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    fn snapshot_id(&self) -> Option<String> {
+        self.source.snapshot_id()
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        let subscribers = self.source.subscribers();
+        self.running.remove(&self.uuid);
+
+        subscribers
+    }
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -106,40 +179,153 @@ impl From<StatisticData> for VoipStatistic {
 
 #[post("/focus", data = "<media>")]
 pub async fn upload_focus_data(
-    mut media: Form<Upload<'_>>,
-) -> Redirect {
-
+    media: Form<Upload<'_>>,
+    running: &State<RunningRetrievals>,
+) -> Result<Redirect, Status> {
     let uuid = uuid::Uuid::new_v4().to_string().replace('-', "");
+    let Upload {
+        file,
+        release,
+        release_date,
+        subscribers,
+        subscriber_mapping,
+        merge,
+        as_of,
+        snapshot,
+        ..
+    } = media.into_inner();
+    // everything is checked before anything is written to output/tmp
+    let mapping: ColumnMapping = match subscriber_mapping.as_deref() {
+        Some(mapping) if !mapping.trim().is_empty() => {
+            serde_json::from_str(mapping).map_err(|_| Status::BadRequest)?
+        }
+        _ => ColumnMapping::default(),
+    };
+    mapping.validate().map_err(|_| Status::BadRequest)?;
 
-    let filename = format!("output/tmp/{uuid}");
-    
-    if media.file.persist_to(filename.clone()).await.is_ok() {
-        thread::spawn(move || {
-            let linked_records = link(process_fcc_data(filename.clone()), retrieve_subscriptions());
-            let tract_summarization = summarize_tracts(linked_records.clone());
-            let broadband_statistic = broadband_subscription_report(&uuid, &tract_summarization);
-            let voip_statistic = voice_subscription_report(&uuid, &tract_summarization);
-            
-            let location_summarization = summarize_locations(linked_records.clone());
-            broadband_availability_report(&uuid, &location_summarization);
-
-            //log::debug!("ENTRIES: {}", linked_records.len());
-            //log::debug!("TRACT SUMMARIZATION\n{tract_summarization:#?}");
-            //log::debug!("LOCATION SUMMARIZATION\n{location_summarization:#?}");
-            log::debug!("BROADBAND STATISTIC\n{broadband_statistic:#?}");
-            log::debug!("VOIP STATISTIC\n{voip_statistic:#?}");
-            
-            std::fs::remove_file(filename).ok();
-
-            let mut broadband_statistics_file = File::create(format!("output/reports/broadband_statistics-{uuid}.json")).unwrap();
-            let _ = broadband_statistics_file.write_all(&serde_json::to_string(&broadband_statistic).unwrap().into_bytes());
-
-            let mut voip_statistics_file = File::create(format!("output/reports/voip_statistics-{uuid}.json")).unwrap();
-            let _ = voip_statistics_file.write_all(&serde_json::to_string(&voip_statistic).unwrap().into_bytes());
-            
-            log::debug!("THREAD COMPLETE");
-        });
+    let release_date = match release_date.as_deref() {
+        Some(date) if !date.is_empty() => Some(
+            chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| Status::BadRequest)?,
+        ),
+        _ => None,
+    };
+
+    let as_of = match as_of.as_deref() {
+        Some(date) if !date.is_empty() => Some(
+            chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| Status::BadRequest)?,
+        ),
+        _ => None,
+    };
+    let subscribers = subscribers.filter(|subscribers| subscribers.len() > 0);
+    let snapshot = snapshot
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+    // a CSV replaces ChargeBee unless it's merged, so a snapshot would go
+    // unused
+    if snapshot.is_some() && subscribers.is_some() && !merge {
+        return Err(Status::BadRequest);
+    }
+    // a snapshot is filed as of the date it was fetched for
+    let as_of = match &snapshot {
+        Some(id) => Snapshot::as_of_for(id, as_of).map_err(|e| match e {
+            SnapshotError::NotFound(_) => Status::NotFound,
+            SnapshotError::AsOf { .. } => Status::BadRequest,
+            _ => Status::InternalServerError,
+        })?,
+        None => as_of,
+    };
+    let mut cancel = None;
+    let chargebee: Option<Box<dyn SubscriberSource>> = match snapshot {
+        Some(id) => Some(Box::new(SnapshotSource { id })),
+        None if merge || subscribers.is_none() => {
+            let chargebee = ChargeBeeSource::new(as_of).on_runtime(Handle::current());
+            cancel = Some(chargebee.cancellation());
+            Some(Box::new(Retrieving {
+                uuid: uuid.clone(),
+                source: chargebee,
+                running: running.inner().clone(),
+            }))
+        }
+        None => None,
+    };
+
+    let file = file.filter(|file| file.len() > 0);
+
+    let mut uploads = vec![];
+
+    let source = match (file, release) {
+        (Some(mut file), _) => {
+            let filename = format!("output/tmp/{uuid}");
+            file.persist_to(filename.clone())
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            uploads.push(filename.clone());
+
+            FabricSource::Upload {
+                filename,
+                release_date: release_date.map(|date| date.format("%Y-%m-%d").to_string()),
+            }
+        }
+        (None, Some(release)) => FabricSource::Release(release),
+        (None, None) => return Err(Status::BadRequest),
+    };
+
+    let csv: Option<Box<dyn SubscriberSource>> = match subscribers {
+        Some(mut subscribers) => {
+            let name = subscribers
+                .name()
+                .map(|name| format!("csv:{name}"))
+                .unwrap_or_else(|| "csv".to_string());
+            let path = format!("output/tmp/{uuid}-subscribers");
+            subscribers
+                .persist_to(path.clone())
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            uploads.push(path.clone());
+
+            Some(Box::new(CsvSource {
+                name,
+                path,
+                mapping,
+            }))
+        }
+        None => None,
+    };
+
+    let subscribers: Box<dyn SubscriberSource> = match (chargebee, csv) {
+        (Some(chargebee), Some(csv)) => Box::new(MergedSource::new(vec![chargebee, csv])),
+        (Some(source), None) | (None, Some(source)) => source,
+        (None, None) => return Err(Status::BadRequest),
+    };
+
+    let running = running.inner().clone();
+
+    if let Some(cancel) = cancel {
+        running.insert(&uuid, cancel);
+    }
+
+    task::spawn_blocking(move || {
+        run(uuid.clone(), source, subscribers, as_of);
+        running.remove(&uuid);
+
+        for upload in uploads {
+            std::fs::remove_file(upload).ok();
+        }
+    });
+
+    Ok(Redirect::to("/"))
+}
+
+// Stops a run that's still fetching from ChargeBee; it's marked failed.
+// Once the subscriptions are in there's nothing left to cancel, and the
+// run goes on to finish.
+#[delete("/focus/<uuid>")]
+pub async fn cancel_focus_run(uuid: &str, running: &State<RunningRetrievals>) -> Status {
+    if running.cancel(uuid) {
+        Status::Accepted
+    } else if RunManifest::load(uuid).is_some() {
+        Status::Conflict
+    } else {
+        Status::NotFound
     }
-    
-    Redirect::to("/")
 }
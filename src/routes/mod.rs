@@ -1,2 +1,7 @@
+pub mod admin;
+pub mod catalog;
+pub mod fabric;
 pub mod focus;
+pub mod overrides;
 pub mod reports;
+pub mod snapshots;
@@ -0,0 +1,43 @@
+use rocket::{delete, get, http::Status, post, serde::json::Json};
+
+use crate::overrides::{Override, OverrideKind, Overrides};
+use crate::routes::admin::Admin;
+
+#[get("/overrides")]
+pub async fn override_list() -> Result<Json<Vec<Override>>, Status> {
+    Overrides::load()
+        .map(|overrides| Json(overrides.entries().to_vec()))
+        .map_err(|e| {
+            log::error!("{e}");
+            Status::InternalServerError
+        })
+}
+
+#[post("/overrides", format = "json", data = "<entry>")]
+pub async fn add_override(_admin: Admin, entry: Json<Override>) -> Status {
+    match Overrides::add(entry.into_inner()) {
+        Ok(()) => Status::Created,
+        Err(e) => {
+            log::error!("{e}");
+            Status::InternalServerError
+        }
+    }
+}
+
+#[delete("/overrides/<kind>/<id>")]
+pub async fn delete_override(_admin: Admin, kind: &str, id: &str) -> Status {
+    let kind = match kind {
+        "customer" => OverrideKind::Customer,
+        "subscription" => OverrideKind::Subscription,
+        _ => return Status::NotFound,
+    };
+
+    match Overrides::remove(kind, id) {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            log::error!("{e}");
+            Status::InternalServerError
+        }
+    }
+}
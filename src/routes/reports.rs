@@ -1,6 +1,8 @@
 use rocket::{get, serde::json::Json, http::Status};
 use glob::glob;
 
+use crate::manifest::RunManifest;
+
 #[get("/reports")]
 pub async fn report_list(
 ) -> Result<Json<Vec<String>>, Status> {
@@ -16,3 +18,10 @@ pub async fn statistics_list(
       x.unwrap().file_name().unwrap().to_str().unwrap().to_string()
     }).collect()))
 }
+
+// the manifest of every run, newest first, so failed runs and their
+// errors show up alongside the reports
+#[get("/runs")]
+pub async fn run_list() -> Result<Json<Vec<RunManifest>>, Status> {
+    Ok(Json(RunManifest::list()))
+}
@@ -0,0 +1,9 @@
+use rocket::{get, http::Status, serde::json::Json};
+
+use crate::snapshot::{Snapshot, SnapshotInfo};
+
+// newest first; pass an id as the snapshot field of /focus to re-run it
+#[get("/snapshots")]
+pub async fn snapshot_list() -> Result<Json<Vec<SnapshotInfo>>, Status> {
+    Ok(Json(Snapshot::list()))
+}
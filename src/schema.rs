@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use csv::ByteRecord;
+
+// The FCC has changed the Fabric column set between releases. Each known
+// layout lists its columns as they appear in the file header alongside the
+// FccByteRecord field they feed. Columns can come in any order; extra
+// columns a layout knows about but we don't use map to None. A renamed
+// column gets a new layout mapping its new name to the same field.
+pub struct FabricSchema {
+    pub version: &'static str,
+    pub columns: &'static [(&'static str, Option<&'static str>)],
+}
+
+pub const FABRIC_SCHEMAS: &[FabricSchema] = &[
+    FabricSchema {
+        version: "v1",
+        columns: &[
+            ("location_id", Some("location_id")),
+            ("address_primary", Some("address_primary")),
+            ("city", Some("city")),
+            ("state", Some("state")),
+            ("zip", Some("zip")),
+            ("zip_suffix", Some("zip_suffix")),
+            ("unit_count", Some("unit_count")),
+            ("bsl_flag", Some("bsl_flag")),
+            ("building_type_code", Some("building_type_code")),
+            ("land_use_code", Some("land_use_code")),
+            ("address_confidence_code", Some("address_confidence_code")),
+            ("county_geoid", Some("county_geoid")),
+            ("block_geoid", Some("block_geoid")),
+            ("h3_9", Some("h3_9")),
+            ("latitude", Some("latitude")),
+            ("longitude", Some("longitude")),
+        ],
+    },
+    // later releases carry the release the row belongs to
+    FabricSchema {
+        version: "v2",
+        columns: &[
+            ("location_id", Some("location_id")),
+            ("address_primary", Some("address_primary")),
+            ("city", Some("city")),
+            ("state", Some("state")),
+            ("zip", Some("zip")),
+            ("zip_suffix", Some("zip_suffix")),
+            ("unit_count", Some("unit_count")),
+            ("bsl_flag", Some("bsl_flag")),
+            ("building_type_code", Some("building_type_code")),
+            ("land_use_code", Some("land_use_code")),
+            ("address_confidence_code", Some("address_confidence_code")),
+            ("county_geoid", Some("county_geoid")),
+            ("block_geoid", Some("block_geoid")),
+            ("h3_9", Some("h3_9")),
+            ("latitude", Some("latitude")),
+            ("longitude", Some("longitude")),
+            ("fcc_rel", None),
+        ],
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct SchemaMismatch {
+    pub closest: &'static str,
+    pub missing: Vec<String>,
+    pub unexpected: Vec<String>,
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unrecognized Fabric layout (closest is {}); missing columns: [{}]; unexpected columns: [{}]",
+            self.closest,
+            self.missing.join(", "),
+            self.unexpected.join(", ")
+        )
+    }
+}
+
+fn header_names(headers: &ByteRecord) -> Vec<String> {
+    headers
+        .iter()
+        .map(|header| String::from_utf8_lossy(header).trim().to_lowercase())
+        .collect()
+}
+
+impl FabricSchema {
+    // Returns the header record to deserialize with: each column position
+    // renamed to the FccByteRecord field it feeds so serde can pick fields
+    // by name regardless of order.
+    pub fn field_headers(&self, headers: &ByteRecord) -> ByteRecord {
+        header_names(headers)
+            .iter()
+            .map(|name| {
+                self.columns
+                    .iter()
+                    .find(|(column, _)| column == name)
+                    .and_then(|(_, field)| *field)
+                    .unwrap_or("")
+            })
+            .collect()
+    }
+
+    fn differences(&self, names: &HashSet<String>) -> (Vec<String>, Vec<String>) {
+        let known: HashSet<&str> = self.columns.iter().map(|(column, _)| *column).collect();
+
+        let mut missing: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|(column, _)| !names.contains(*column))
+            .map(|(column, _)| column.to_string())
+            .collect();
+        let mut unexpected: Vec<String> = names
+            .iter()
+            .filter(|name| !known.contains(name.as_str()))
+            .cloned()
+            .collect();
+
+        missing.sort();
+        unexpected.sort();
+
+        (missing, unexpected)
+    }
+}
+
+pub fn detect_schema(headers: &ByteRecord) -> Result<&'static FabricSchema, SchemaMismatch> {
+    let names: HashSet<String> = header_names(headers).into_iter().collect();
+    let mut closest: Option<SchemaMismatch> = None;
+
+    for schema in FABRIC_SCHEMAS {
+        let (missing, unexpected) = schema.differences(&names);
+
+        if missing.is_empty() && unexpected.is_empty() {
+            return Ok(schema);
+        }
+
+        if closest.as_ref().is_none_or(|closest| {
+            missing.len() + unexpected.len() < closest.missing.len() + closest.unexpected.len()
+        }) {
+            closest = Some(SchemaMismatch {
+                closest: schema.version,
+                missing,
+                unexpected,
+            });
+        }
+    }
+
+    Err(closest.unwrap_or(SchemaMismatch {
+        closest: "none",
+        missing: vec![],
+        unexpected: names.into_iter().collect(),
+    }))
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use glob::glob;
+use serde::{Deserialize, Serialize};
+
+use crate::emerald::SubscriptionApiItem;
+use crate::subscriber::{SourceError, Subscriber, SubscriberSource};
+
+const SNAPSHOT_DIRECTORY: &str = "output/snapshots";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Key,
+    Encryption,
+    Decryption,
+    NotFound(String),
+    // the as_of a run asked for isn't the one the snapshot was fetched for
+    AsOf {
+        snapshot: Option<String>,
+        requested: String,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "unable to access the subscriber snapshot: {e}"),
+            SnapshotError::Json(e) => write!(f, "unable to read the subscriber snapshot: {e}"),
+            SnapshotError::Key => write!(f, "SNAPSHOT_KEY must be 64 hex characters"),
+            SnapshotError::Encryption => write!(f, "unable to encrypt the subscriber snapshot"),
+            SnapshotError::Decryption => write!(
+                f,
+                "unable to decrypt the subscriber snapshot; is SNAPSHOT_KEY the key it was saved with?"
+            ),
+            SnapshotError::NotFound(id) => write!(f, "no subscriber snapshot {id}"),
+            SnapshotError::AsOf {
+                snapshot: Some(snapshot),
+                requested,
+            } => write!(
+                f,
+                "the subscriber snapshot is as of {snapshot}, not {requested}"
+            ),
+            SnapshotError::AsOf {
+                snapshot: None,
+                requested,
+            } => write!(
+                f,
+                "the subscriber snapshot has current subscriptions and can't be filed as of {requested}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(e) => Some(e),
+            SnapshotError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+// Exactly what ChargeBee returned for a run, so the filing can be
+// reproduced later without the API. as_of is the date the run filed as of,
+// if any, since the subscriptions were fetched for it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub created_at: i64,
+    // YYYY-MM-DD, as in the run manifest
+    pub as_of: Option<String>,
+    pub subscriptions: Vec<SubscriptionApiItem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub encrypted: bool,
+    pub bytes: u64,
+    pub saved_at: i64,
+}
+
+fn key() -> Result<Option<Key<Aes256Gcm>>, SnapshotError> {
+    let Some(key) = crate::SNAPSHOT_KEY.as_deref() else {
+        return Ok(None);
+    };
+
+    let bytes = hex::decode(key.trim()).map_err(|_| SnapshotError::Key)?;
+
+    if bytes.len() != 32 {
+        return Err(SnapshotError::Key);
+    }
+
+    Ok(Some(*Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+fn path(id: &str, encrypted: bool) -> String {
+    if encrypted {
+        format!("{SNAPSHOT_DIRECTORY}/{id}.json.gz.aes")
+    } else {
+        format!("{SNAPSHOT_DIRECTORY}/{id}.json.gz")
+    }
+}
+
+// ids are generated here, but they come back in from routes so anything
+// that could walk out of the snapshot directory is turned away
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+impl Snapshot {
+    pub fn new(subscriptions: Vec<SubscriptionApiItem>, as_of: Option<NaiveDate>) -> Self {
+        Snapshot {
+            id: uuid::Uuid::new_v4().to_string().replace('-', ""),
+            created_at: chrono::Utc::now().timestamp(),
+            as_of: as_of.map(|date| date.format("%Y-%m-%d").to_string()),
+            subscriptions,
+        }
+    }
+
+    // Gzipped JSON, and with SNAPSHOT_KEY set the whole thing (customer
+    // names, addresses and contact details included) is sealed with
+    // AES-256-GCM, the random nonce written ahead of the ciphertext.
+    pub fn save(&self) -> Result<(), SnapshotError> {
+        fs::create_dir_all(SNAPSHOT_DIRECTORY)?;
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        serde_json::to_writer(&mut encoder, self)?;
+        let compressed = encoder.finish()?;
+
+        match key()? {
+            Some(key) => {
+                let cipher = Aes256Gcm::new(&key);
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                let ciphertext = cipher
+                    .encrypt(&nonce, compressed.as_slice())
+                    .map_err(|_| SnapshotError::Encryption)?;
+
+                let mut file = File::create(path(&self.id, true))?;
+                file.write_all(&nonce)?;
+                file.write_all(&ciphertext)?;
+            }
+            None => fs::write(path(&self.id, false), compressed)?,
+        }
+
+        Ok(())
+    }
+
+    pub fn load(id: &str) -> Result<Self, SnapshotError> {
+        if !valid_id(id) {
+            return Err(SnapshotError::NotFound(id.to_string()));
+        }
+
+        let compressed = if Path::new(&path(id, true)).exists() {
+            let sealed = fs::read(path(id, true))?;
+
+            if sealed.len() < NONCE_LENGTH {
+                return Err(SnapshotError::Decryption);
+            }
+
+            let key = key()?.ok_or(SnapshotError::Decryption)?;
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+            Aes256Gcm::new(&key)
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| SnapshotError::Decryption)?
+        } else if Path::new(&path(id, false)).exists() {
+            fs::read(path(id, false))?
+        } else {
+            return Err(SnapshotError::NotFound(id.to_string()));
+        };
+
+        let mut json = vec![];
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut json)?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    // The as_of a run against snapshot id files as: the snapshot's own, which
+    // a requested date has to agree with.
+    pub fn as_of_for(
+        id: &str,
+        requested: Option<NaiveDate>,
+    ) -> Result<Option<NaiveDate>, SnapshotError> {
+        let snapshot = Snapshot::load(id)?;
+        let as_of = snapshot
+            .as_of
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+
+        match requested {
+            Some(requested) if Some(requested) != as_of => Err(SnapshotError::AsOf {
+                snapshot: snapshot.as_of,
+                requested: requested.format("%Y-%m-%d").to_string(),
+            }),
+            _ => Ok(as_of),
+        }
+    }
+
+    pub fn list() -> Vec<SnapshotInfo> {
+        let mut snapshots: Vec<SnapshotInfo> = glob(&format!("{SNAPSHOT_DIRECTORY}/*.json.gz*"))
+            .expect("Failed to read glob pattern")
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name()?.to_str()?;
+                let (id, extension) = name.split_once('.')?;
+                let metadata = entry.metadata().ok()?;
+                let saved_at: chrono::DateTime<chrono::Utc> = metadata.modified().ok()?.into();
+
+                Some(SnapshotInfo {
+                    id: id.to_string(),
+                    encrypted: extension.ends_with(".aes"),
+                    bytes: metadata.len(),
+                    saved_at: saved_at.timestamp(),
+                })
+            })
+            .collect();
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.saved_at));
+
+        snapshots
+    }
+}
+
+// Re-runs against a saved snapshot instead of ChargeBee. The run has to be
+// as of the snapshot's date (see Snapshot::as_of_for), since that's what
+// the subscriptions were fetched for.
+pub struct SnapshotSource {
+    pub id: String,
+}
+
+impl SubscriberSource for SnapshotSource {
+    fn name(&self) -> &str {
+        "snapshot"
+    }
+
+    fn snapshot_id(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    fn replays_snapshot(&self) -> bool {
+        true
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        Ok(Snapshot::load(&self.id)?
+            .subscriptions
+            .into_iter()
+            .map(Subscriber::from)
+            .collect())
+    }
+}
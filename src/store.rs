@@ -0,0 +1,676 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use rusqlite::{params, Connection, OptionalExtension, Row, Rows};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::carryover::PriorLink;
+use crate::fcc::{process_fcc_data, FccRecord, IngestError, IngestReport, Interner};
+
+// A Fabric release is only parsed once: its rows are kept in the sqlite
+// database Rocket.toml declares as sqlite_database, keyed by the release
+// date the operator gives it and the SHA-256 of the uploaded file, so the
+// same upload is never stored twice. A release stays pending, invisible to
+// runs and the routes, until all of its rows are in.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS fabric_releases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    release_date TEXT NOT NULL,
+    file_hash TEXT NOT NULL UNIQUE,
+    fabric_version TEXT NOT NULL,
+    location_count INTEGER NOT NULL,
+    imported_at INTEGER NOT NULL,
+    ingest_report TEXT NOT NULL,
+    status TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fabric_locations (
+    release_id INTEGER NOT NULL REFERENCES fabric_releases (id),
+    location_id TEXT NOT NULL,
+    address_primary TEXT,
+    city TEXT,
+    state TEXT NOT NULL,
+    zip INTEGER,
+    zip_suffix INTEGER,
+    unit_count INTEGER NOT NULL,
+    bsl_flag INTEGER NOT NULL,
+    building_type_code TEXT NOT NULL,
+    land_use_code INTEGER NOT NULL,
+    address_confidence_code INTEGER NOT NULL,
+    county_geoid TEXT NOT NULL,
+    block_geoid TEXT NOT NULL,
+    h3_9 TEXT NOT NULL,
+    latitude REAL NOT NULL,
+    longitude REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS fabric_locations_release
+    ON fabric_locations (release_id, location_id);
+CREATE TABLE IF NOT EXISTS subscriber_links (
+    source TEXT NOT NULL,
+    subscription_id TEXT NOT NULL,
+    customer_id TEXT,
+    location_id TEXT NOT NULL,
+    service_address TEXT NOT NULL,
+    fabric_address TEXT NOT NULL,
+    fabric_release INTEGER NOT NULL,
+    linked_at INTEGER NOT NULL,
+    PRIMARY KEY (source, subscription_id)
+);
+";
+
+// rows are committed in batches of this many so an import doesn't hold the
+// database's write lock for the minutes a national Fabric takes to load
+const IMPORT_BATCH_ROWS: u64 = 10_000;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Database(rusqlite::Error),
+    Io(io::Error),
+    Ingest(IngestError),
+    NotFound(i64),
+    // release_date isn't a YYYY-MM-DD date
+    InvalidReleaseDate(String),
+    // the upload is already stored, under another release date
+    ReleaseDate {
+        id: i64,
+        stored: String,
+        requested: String,
+    },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Database(e) => write!(f, "Fabric store error: {e}"),
+            StoreError::Io(e) => write!(f, "unable to read the Fabric upload: {e}"),
+            StoreError::Ingest(e) => write!(f, "{e}"),
+            StoreError::NotFound(id) => write!(f, "no stored Fabric release with id {id}"),
+            StoreError::InvalidReleaseDate(date) => {
+                write!(f, "release date {date:?} is not a YYYY-MM-DD date")
+            }
+            StoreError::ReleaseDate {
+                id,
+                stored,
+                requested,
+            } => write!(
+                f,
+                "this Fabric is already stored as release {id} dated {stored}, not {requested}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Database(e) => Some(e),
+            StoreError::Io(e) => Some(e),
+            StoreError::Ingest(e) => Some(e),
+            StoreError::NotFound(_)
+            | StoreError::InvalidReleaseDate(_)
+            | StoreError::ReleaseDate { .. } => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<IngestError> for StoreError {
+    fn from(e: IngestError) -> Self {
+        StoreError::Ingest(e)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FabricRelease {
+    pub id: i64,
+    pub release_date: String,
+    pub file_hash: String,
+    pub fabric_version: String,
+    pub location_count: i64,
+    pub imported_at: i64,
+}
+
+impl FabricRelease {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(FabricRelease {
+            id: row.get("id")?,
+            release_date: row.get("release_date")?,
+            file_hash: row.get("file_hash")?,
+            fabric_version: row.get("fabric_version")?,
+            location_count: row.get("location_count")?,
+            imported_at: row.get("imported_at")?,
+        })
+    }
+}
+
+// what GET /fabric/<id> returns: the release, the ingest report from when
+// it was imported and how its locations break down by state and county
+#[derive(Debug, Serialize)]
+pub struct FabricReleaseDetail {
+    pub release: FabricRelease,
+    pub ingest_report: serde_json::Value,
+    pub states: BTreeMap<String, i64>,
+    pub counties: BTreeMap<String, i64>,
+}
+
+const RELEASE_COLUMNS: &str =
+    "id, release_date, file_hash, fabric_version, location_count, imported_at";
+
+fn database_path() -> String {
+    rocket::Config::figment()
+        .extract_inner::<String>("databases.sqlite_database.url")
+        .unwrap_or_else(|_| "data.db".to_string())
+}
+
+fn file_hash(filename: &str) -> io::Result<String> {
+    let mut file = File::open(filename)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub struct FabricStore {
+    connection: Connection,
+}
+
+impl FabricStore {
+    pub fn open() -> Result<Self, StoreError> {
+        let connection = Connection::open(database_path())?;
+        // runs and the routes each hold their own connection
+        connection.busy_timeout(std::time::Duration::from_secs(30))?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(FabricStore { connection })
+    }
+
+    pub fn releases(&self) -> Result<Vec<FabricRelease>, StoreError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {RELEASE_COLUMNS} FROM fabric_releases WHERE status = 'ready'
+             ORDER BY release_date DESC, id DESC"
+        ))?;
+        let releases = statement
+            .query_map([], FabricRelease::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(releases)
+    }
+
+    pub fn release(&self, id: i64) -> Result<Option<FabricRelease>, StoreError> {
+        Ok(self
+            .connection
+            .query_row(
+                &format!("SELECT {RELEASE_COLUMNS} FROM fabric_releases WHERE id = ?1 AND status = 'ready'"),
+                [id],
+                FabricRelease::from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn ingest_report(&self, id: i64) -> Result<serde_json::Value, StoreError> {
+        let report: String = self.connection.query_row(
+            "SELECT ingest_report FROM fabric_releases WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )?;
+
+        Ok(serde_json::from_str(&report).unwrap_or(serde_json::Value::Null))
+    }
+
+    pub fn detail(&self, id: i64) -> Result<Option<FabricReleaseDetail>, StoreError> {
+        let Some(release) = self.release(id)? else {
+            return Ok(None);
+        };
+
+        let count_by = |column: &str| -> Result<BTreeMap<String, i64>, StoreError> {
+            let mut statement = self.connection.prepare(&format!(
+                "SELECT {column}, COUNT(*) FROM fabric_locations WHERE release_id = ?1 GROUP BY {column}"
+            ))?;
+            let counts = statement
+                .query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<BTreeMap<_, _>>>()?;
+
+            Ok(counts)
+        };
+
+        Ok(Some(FabricReleaseDetail {
+            ingest_report: self.ingest_report(id)?,
+            states: count_by("state")?,
+            counties: count_by("county_geoid")?,
+            release,
+        }))
+    }
+
+    pub fn delete_release(&mut self, id: i64) -> Result<bool, StoreError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM fabric_locations WHERE release_id = ?1", [id])?;
+        let deleted = transaction.execute("DELETE FROM fabric_releases WHERE id = ?1", [id])?;
+        transaction.commit()?;
+
+        Ok(deleted > 0)
+    }
+
+    // Stores the Fabric in filename under release_date, today if it isn't
+    // given. If the same file has been imported before the existing release
+    // is returned as is, along with the ingest report recorded when it was
+    // first imported; a release_date that doesn't match the stored one is
+    // refused rather than quietly ignored.
+    pub fn import(
+        &mut self,
+        filename: &str,
+        release_date: Option<&str>,
+    ) -> Result<(FabricRelease, serde_json::Value), StoreError> {
+        if let Some(date) = release_date {
+            if chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err() {
+                return Err(StoreError::InvalidReleaseDate(date.to_string()));
+            }
+        }
+
+        let hash = file_hash(filename)?;
+
+        let existing = self
+            .connection
+            .query_row(
+                "SELECT id, status FROM fabric_releases WHERE file_hash = ?1",
+                [&hash],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+
+        match existing {
+            Some((id, status)) if status == "ready" => {
+                log::debug!("FABRIC {hash} ALREADY STORED AS RELEASE {id}");
+                let release = self.release(id)?.ok_or(StoreError::NotFound(id))?;
+
+                if let Some(requested) = release_date.filter(|date| *date != release.release_date) {
+                    return Err(StoreError::ReleaseDate {
+                        id,
+                        stored: release.release_date,
+                        requested: requested.to_string(),
+                    });
+                }
+
+                return Ok((release, self.ingest_report(id)?));
+            }
+            // an earlier import of the same file that never finished
+            Some((id, _)) => {
+                log::warn!("DISCARDING UNFINISHED IMPORT OF FABRIC {hash} AS RELEASE {id}");
+                self.delete_release(id)?;
+            }
+            None => {}
+        }
+
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let release_date = release_date.unwrap_or(&today);
+
+        self.connection.execute(
+            "INSERT INTO fabric_releases
+                (release_date, file_hash, fabric_version, location_count, imported_at, ingest_report, status)
+             VALUES (?1, ?2, '', 0, ?3, '{}', 'pending')",
+            params![release_date, hash, chrono::Utc::now().timestamp()],
+        )?;
+        let id = self.connection.last_insert_rowid();
+
+        let report = match self.import_locations(id, filename) {
+            Ok(report) => report,
+            Err(e) => {
+                if let Err(e) = self.delete_release(id) {
+                    log::error!("unable to discard the unfinished release {id}: {e}");
+                }
+                return Err(e);
+            }
+        };
+
+        let ingest_report = serde_json::to_value(&report).unwrap_or(serde_json::Value::Null);
+        self.connection.execute(
+            "UPDATE fabric_releases
+             SET fabric_version = ?2, location_count = ?3, ingest_report = ?4, status = 'ready'
+             WHERE id = ?1",
+            params![
+                id,
+                report.fabric_version,
+                report.accepted_rows as i64,
+                ingest_report.to_string()
+            ],
+        )?;
+
+        let release = self.release(id)?.ok_or(StoreError::NotFound(id))?;
+
+        Ok((release, ingest_report))
+    }
+
+    fn import_locations(&self, id: i64, filename: &str) -> Result<IngestReport, StoreError> {
+        let mut insert = self.connection.prepare(
+            "INSERT INTO fabric_locations VALUES
+                (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        )?;
+        let mut failure = None;
+        let mut batched = 0;
+
+        self.connection.execute_batch("BEGIN")?;
+        let report = process_fcc_data(filename.to_string(), |record| {
+            if failure.is_some() {
+                return;
+            }
+
+            let inserted = insert.execute(params![
+                id,
+                record.location_id,
+                record.address_primary,
+                record.city.as_deref(),
+                &*record.state,
+                record.zip,
+                record.zip_suffix,
+                record.unit_count as i64,
+                record.bsl_flag,
+                record.building_type_code.to_string(),
+                record.land_use_code as i64,
+                record.address_confidence_code as i64,
+                &*record.county_geoid,
+                record.block_geoid,
+                &*record.h3_9,
+                record.latitude,
+                record.longitude,
+            ]);
+
+            batched += 1;
+            let committed = match inserted {
+                Ok(_) if batched == IMPORT_BATCH_ROWS => {
+                    batched = 0;
+                    self.connection.execute_batch("COMMIT; BEGIN")
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = committed {
+                failure = Some(e);
+            }
+        });
+
+        let result = match (report, failure) {
+            (Err(e), _) => Err(e.into()),
+            (_, Some(e)) => Err(e.into()),
+            (Ok(report), None) => self
+                .connection
+                .execute_batch("COMMIT")
+                .map(|_| report)
+                .map_err(StoreError::from),
+        };
+
+        if result.is_err() {
+            self.connection.execute_batch("ROLLBACK").ok();
+        }
+
+        result
+    }
+
+    // rows come back in the order they were read from the Fabric so a run
+    // from a stored release links exactly as one from the upload would
+    pub fn visit_locations(
+        &self,
+        release_id: i64,
+        mut visit: impl FnMut(FccRecord),
+    ) -> Result<(), StoreError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {LOCATION_COLUMNS} FROM fabric_locations WHERE release_id = ?1 ORDER BY rowid"
+        ))?;
+        let mut rows = statement.query([release_id])?;
+        let mut interner = Interner::default();
+
+        while let Some(row) = rows.next()? {
+            visit(location(row, &mut interner)?);
+        }
+
+        Ok(())
+    }
+
+    // Walks two releases side by side in location_id order, handing visit
+    // the record each release has for a location_id, or None if it has
+    // none. A location_id stored more than once is represented by the
+    // first of its rows, as it is in FccIndex::location.
+    pub fn visit_location_pairs(
+        &self,
+        from: i64,
+        to: i64,
+        mut visit: impl FnMut(Option<&FccRecord>, Option<&FccRecord>),
+    ) -> Result<(), StoreError> {
+        let query = format!(
+            "SELECT {LOCATION_COLUMNS} FROM fabric_locations
+             WHERE release_id = ?1 ORDER BY location_id, rowid"
+        );
+        let mut from_statement = self.connection.prepare(&query)?;
+        let mut to_statement = self.connection.prepare(&query)?;
+        let mut from = SortedLocations::new(from_statement.query([from])?);
+        let mut to = SortedLocations::new(to_statement.query([to])?);
+        let mut interner = Interner::default();
+
+        let mut old = from.next(&mut interner)?;
+        let mut new = to.next(&mut interner)?;
+
+        loop {
+            let order = match (&old, &new) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(old), Some(new)) => old.location_id.cmp(&new.location_id),
+            };
+
+            match order {
+                Ordering::Less => {
+                    visit(old.as_ref(), None);
+                    old = from.next(&mut interner)?;
+                }
+                Ordering::Greater => {
+                    visit(None, new.as_ref());
+                    new = to.next(&mut interner)?;
+                }
+                Ordering::Equal => {
+                    visit(old.as_ref(), new.as_ref());
+                    old = from.next(&mut interner)?;
+                    new = to.next(&mut interner)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn subscriber_links(&self, source: &str) -> Result<Vec<PriorLink>, StoreError> {
+        let mut statement = self.connection.prepare(
+            "SELECT subscription_id, customer_id, location_id, service_address, fabric_address
+             FROM subscriber_links WHERE source = ?1",
+        )?;
+        let links = statement
+            .query_map([source], |row| {
+                Ok(PriorLink {
+                    subscription_id: row.get(0)?,
+                    customer_id: row.get(1)?,
+                    location_id: row.get(2)?,
+                    service_address: row.get(3)?,
+                    fabric_address: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(links)
+    }
+
+    pub fn save_subscriber_links(
+        &mut self,
+        source: &str,
+        release_id: i64,
+        links: &[PriorLink],
+    ) -> Result<(), StoreError> {
+        let now = chrono::Utc::now().timestamp();
+        let transaction = self.connection.transaction()?;
+
+        {
+            let mut insert = transaction.prepare(
+                "INSERT OR REPLACE INTO subscriber_links VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+
+            for link in links {
+                insert.execute(params![
+                    source,
+                    link.subscription_id,
+                    link.customer_id,
+                    link.location_id,
+                    link.service_address,
+                    link.fabric_address,
+                    release_id,
+                    now,
+                ])?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
+
+const LOCATION_COLUMNS: &str =
+    "location_id, address_primary, city, state, zip, zip_suffix, unit_count, bsl_flag,
+     building_type_code, land_use_code, address_confidence_code, county_geoid, block_geoid,
+     h3_9, latitude, longitude";
+
+fn location(row: &Row, interner: &mut Interner) -> rusqlite::Result<FccRecord> {
+    let city: Option<String> = row.get(2)?;
+    let state: String = row.get(3)?;
+    let building_type_code: String = row.get(8)?;
+    let county_geoid: String = row.get(11)?;
+    let h3_9: String = row.get(13)?;
+
+    Ok(FccRecord {
+        location_id: row.get(0)?,
+        address_primary: row.get(1)?,
+        city: city.map(|city| interner.intern_str(&city)),
+        state: interner.intern_str(&state),
+        zip: row.get(4)?,
+        zip_suffix: row.get(5)?,
+        unit_count: row.get::<_, i64>(6)? as u64,
+        bsl_flag: row.get(7)?,
+        building_type_code: building_type_code.chars().next().unwrap_or(' '),
+        land_use_code: row.get::<_, i64>(9)? as u64,
+        address_confidence_code: row.get::<_, i64>(10)? as u64,
+        county_geoid: interner.intern_str(&county_geoid),
+        block_geoid: row.get(12)?,
+        h3_9: interner.intern_str(&h3_9),
+        latitude: row.get(14)?,
+        longitude: row.get(15)?,
+    })
+}
+
+// one release's locations in location_id order, skipping the repeats of a
+// location_id after its first row
+struct SortedLocations<'s> {
+    rows: Rows<'s>,
+    last: Option<String>,
+}
+
+impl<'s> SortedLocations<'s> {
+    fn new(rows: Rows<'s>) -> Self {
+        SortedLocations { rows, last: None }
+    }
+
+    fn next(&mut self, interner: &mut Interner) -> rusqlite::Result<Option<FccRecord>> {
+        while let Some(row) = self.rows.next()? {
+            let location_id: String = row.get(0)?;
+
+            if self.last.as_ref() != Some(&location_id) {
+                self.last = Some(location_id);
+                return location(row, interner).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(releases: &[(i64, &[(&str, &str)])]) -> FabricStore {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+
+        for (release_id, locations) in releases {
+            connection
+                .execute(
+                    "INSERT INTO fabric_releases VALUES (?1, '2025-06-30', ?1, 'v1', 0, 0, '{}', 'ready')",
+                    [release_id],
+                )
+                .unwrap();
+
+            for (location_id, address) in locations.iter() {
+                connection
+                    .execute(
+                        "INSERT INTO fabric_locations VALUES
+                            (?1, ?2, ?3, 'Eugene', 'OR', NULL, NULL, 1, 1, 'R', 1, 1,
+                             '41039', '410390001001000', '8928', 44.05, -123.09)",
+                        params![release_id, location_id, address],
+                    )
+                    .unwrap();
+            }
+        }
+
+        FabricStore { connection }
+    }
+
+    #[test]
+    fn location_pairs_merge_both_releases_in_location_id_order() {
+        let store = store(&[
+            (
+                1,
+                &[
+                    ("4", "4 Oak St"),
+                    ("1", "1 Elm St"),
+                    ("2", "2 Elm St"),
+                    ("2", "2 Fir St"),
+                ],
+            ),
+            (
+                2,
+                &[("2", "2 Elm Street"), ("3", "3 Elm St"), ("4", "4 Oak St")],
+            ),
+        ]);
+
+        let mut pairs = vec![];
+        store
+            .visit_location_pairs(1, 2, |old, new| {
+                let address = |record: Option<&FccRecord>| {
+                    record.and_then(|record| record.address_primary.clone())
+                };
+                pairs.push((address(old), address(new)));
+            })
+            .unwrap();
+
+        let address = |text: &str| Some(text.to_string());
+        assert_eq!(
+            pairs,
+            vec![
+                (address("1 Elm St"), None),
+                // the first of a repeated location_id's rows stands for it
+                (address("2 Elm St"), address("2 Elm Street")),
+                (None, address("3 Elm St")),
+                (address("4 Oak St"), address("4 Oak St")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,705 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::emerald::{ChargeBeeError, ServiceMedium};
+use crate::normalize::{normalize_address, normalize_city, NormalizedAddress};
+use crate::snapshot::SnapshotError;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CustomerClass {
+    Residential,
+    Business,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    Future,
+    InTrial,
+    Active,
+    NonRenewing,
+    Paused,
+    Cancelled,
+}
+
+// One billed service at one service address, in the shape every billing
+// system is reduced to before linking. Dates are unix timestamps. Sources
+// that don't have a plan_id in the product catalog can give the service's
+// technology code and speeds (in Mbps) directly instead.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Subscriber {
+    pub subscription_id: Option<String>,
+    pub customer_id: Option<String>,
+    pub plan_id: Option<String>,
+    pub service_address: Option<String>,
+    pub service_city_st_zip: Option<String>,
+    pub census_block: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub class: Option<CustomerClass>,
+    pub status: Option<SubscriberStatus>,
+    pub started_at: Option<i64>,
+    pub activated_at: Option<i64>,
+    pub cancelled_at: Option<i64>,
+    pub current_term_end: Option<i64>,
+    pub technology_code: Option<u8>,
+    pub download_speed: Option<u16>,
+    pub upload_speed: Option<u16>,
+}
+
+impl Subscriber {
+    pub fn normalized_service_address(&self) -> Option<NormalizedAddress> {
+        self.service_address.as_deref().and_then(normalize_address)
+    }
+
+    // service_city_st_zip is free text along the lines of
+    // "Springfield, OR 97477"; only the city is useful for linking
+    pub fn normalized_service_city(&self) -> Option<String> {
+        self.service_city_st_zip
+            .as_deref()
+            .and_then(|city_state_zip| city_state_zip.split_once(','))
+            .map(|(city, _other)| normalize_city(city))
+    }
+
+    // the whole service address on one line, the way a geocoder wants it
+    pub fn one_line_service_address(&self) -> Option<String> {
+        Some(format!(
+            "{}, {}",
+            self.service_address.as_deref()?,
+            self.service_city_st_zip.as_deref()?
+        ))
+    }
+
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        Some((self.latitude?, self.longitude?))
+    }
+
+    // Whether this was a live subscription at the cutoff (see cutoff
+    // below). A record without a start date is taken to have been live
+    // all along, as exports often don't carry one. Pauses aren't dated, so
+    // one that's paused now counts as live.
+    pub fn subscribed_on(&self, cutoff: i64) -> bool {
+        if self.status == Some(SubscriberStatus::Future) {
+            return false;
+        }
+
+        if self
+            .started_at
+            .or(self.activated_at)
+            .is_some_and(|start| start >= cutoff)
+        {
+            return false;
+        }
+
+        match self.cancelled_at {
+            Some(cancelled_at) => cancelled_at >= cutoff,
+            None => self.status != Some(SubscriberStatus::Cancelled),
+        }
+    }
+
+    // a customer with both internet and voice is still one subscriber
+    pub fn subscriber_id(&self) -> Option<&String> {
+        self.customer_id.as_ref().or(self.subscription_id.as_ref())
+    }
+}
+
+// Filings are as of the end of the day (UTC), so the cutoff is midnight at
+// the start of the next one.
+pub fn cutoff(as_of: chrono::NaiveDate) -> i64 {
+    (as_of + chrono::Days::new(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc()
+        .timestamp()
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    Mapping(String),
+    Row { line: u64, message: String },
+    Snapshot(SnapshotError),
+    ChargeBee(ChargeBeeError),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Io(e) => write!(f, "unable to read subscribers: {e}"),
+            SourceError::Csv(e) => write!(f, "unable to read the subscriber CSV: {e}"),
+            SourceError::Json(e) => write!(f, "unable to read the subscriber snapshot: {e}"),
+            SourceError::Mapping(message) => {
+                write!(f, "invalid subscriber column mapping: {message}")
+            }
+            SourceError::Row { line, message } => {
+                write!(f, "invalid subscriber on line {line}: {message}")
+            }
+            SourceError::Snapshot(e) => write!(f, "{e}"),
+            SourceError::ChargeBee(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SourceError::Io(e) => Some(e),
+            SourceError::Csv(e) => Some(e),
+            SourceError::Json(e) => Some(e),
+            SourceError::Mapping(_) => None,
+            SourceError::Row { .. } => None,
+            SourceError::Snapshot(e) => Some(e),
+            SourceError::ChargeBee(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for SourceError {
+    fn from(e: io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+impl From<csv::Error> for SourceError {
+    fn from(e: csv::Error) -> Self {
+        SourceError::Csv(e)
+    }
+}
+
+impl From<ChargeBeeError> for SourceError {
+    fn from(e: ChargeBeeError) -> Self {
+        SourceError::ChargeBee(e)
+    }
+}
+
+impl From<SnapshotError> for SourceError {
+    fn from(e: SnapshotError) -> Self {
+        SourceError::Snapshot(e)
+    }
+}
+
+impl From<serde_json::Error> for SourceError {
+    fn from(e: serde_json::Error) -> Self {
+        SourceError::Json(e)
+    }
+}
+
+// Anything that can list the subscribers to link against the Fabric.
+// ChargeBee is the usual one (emerald::ChargeBeeSource); the file sources
+// below cover billing systems that can only export.
+pub trait SubscriberSource: Send {
+    fn name(&self) -> &str;
+
+    // the saved snapshot (see snapshot.rs) the subscribers were read from
+    // or written to, once subscribers has been called
+    fn snapshot_id(&self) -> Option<String> {
+        None
+    }
+
+    // whether the subscribers are replayed from a saved snapshot rather
+    // than fetched as they are now
+    fn replays_snapshot(&self) -> bool {
+        false
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError>;
+}
+
+// the Subscriber fields a CSV column can feed
+pub const CSV_FIELDS: &[&str] = &[
+    "subscription_id",
+    "customer_id",
+    "plan_id",
+    "service_address",
+    "service_city_st_zip",
+    "census_block",
+    "latitude",
+    "longitude",
+    "class",
+    "status",
+    "started_at",
+    "activated_at",
+    "cancelled_at",
+    "current_term_end",
+    "technology_code",
+    "download_speed",
+    "upload_speed",
+];
+
+// Which CSV column feeds each Subscriber field, e.g.
+// {"service_address": "Street Address", "class": "Res/Bus"}. A field that
+// isn't mapped is read from a column with the field's own name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ColumnMapping(pub HashMap<String, String>);
+
+impl ColumnMapping {
+    pub fn validate(&self) -> Result<(), SourceError> {
+        let mut unknown: Vec<&str> = self
+            .0
+            .keys()
+            .map(String::as_str)
+            .filter(|field| !CSV_FIELDS.contains(field))
+            .collect();
+        unknown.sort();
+
+        if !unknown.is_empty() {
+            return Err(SourceError::Mapping(format!(
+                "unknown fields [{}]",
+                unknown.join(", ")
+            )));
+        }
+
+        // a column can only feed one field
+        let mut fields: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+
+        for (field, mapped) in &self.0 {
+            fields.entry(column(mapped)).or_default().push(field);
+        }
+
+        let shared: Vec<String> = fields
+            .into_iter()
+            .filter(|(_, fields)| fields.len() > 1)
+            .map(|(mapped, mut fields)| {
+                fields.sort();
+                format!("{mapped:?} is mapped to [{}]", fields.join(", "))
+            })
+            .collect();
+
+        if shared.is_empty() {
+            Ok(())
+        } else {
+            Err(SourceError::Mapping(shared.join("; ")))
+        }
+    }
+
+    // the header record to deserialize with, each column renamed to the
+    // field it feeds (or blanked so it's ignored); every mapped column has
+    // to be there, so a typo doesn't quietly leave a field empty
+    fn field_headers(&self, headers: &StringRecord) -> Result<StringRecord, SourceError> {
+        let mut missing: Vec<&str> = self
+            .0
+            .values()
+            .filter(|mapped| {
+                !headers
+                    .iter()
+                    .any(|header| column(header) == column(mapped))
+            })
+            .map(String::as_str)
+            .collect();
+        missing.sort();
+
+        if !missing.is_empty() {
+            return Err(SourceError::Mapping(format!(
+                "no columns [{}] in the header",
+                missing.join(", ")
+            )));
+        }
+
+        Ok(headers
+            .iter()
+            .map(|header| {
+                let mapped = self
+                    .0
+                    .iter()
+                    .find(|(_, mapped)| column(mapped) == column(header))
+                    .map(|(field, _)| field.as_str());
+                let own = CSV_FIELDS
+                    .iter()
+                    .find(|field| **field == column(header) && !self.0.contains_key(**field))
+                    .copied();
+
+                mapped.or(own).unwrap_or("")
+            })
+            .collect())
+    }
+}
+
+// headers are matched without case or surrounding space
+fn column(header: &str) -> String {
+    header.trim().to_lowercase()
+}
+
+// every column is read as text so a bad value can be reported against its
+// line and field rather than as a bare serde error
+#[derive(Debug, Default, Deserialize)]
+struct CsvRow {
+    subscription_id: Option<String>,
+    customer_id: Option<String>,
+    plan_id: Option<String>,
+    service_address: Option<String>,
+    service_city_st_zip: Option<String>,
+    census_block: Option<String>,
+    latitude: Option<String>,
+    longitude: Option<String>,
+    class: Option<String>,
+    status: Option<String>,
+    started_at: Option<String>,
+    activated_at: Option<String>,
+    cancelled_at: Option<String>,
+    current_term_end: Option<String>,
+    technology_code: Option<String>,
+    download_speed: Option<String>,
+    upload_speed: Option<String>,
+}
+
+fn present(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+fn parse<T: std::str::FromStr>(field: &str, value: Option<String>) -> Result<Option<T>, String> {
+    present(value)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{field} {value:?} isn't valid"))
+        })
+        .transpose()
+}
+
+fn parse_class(value: Option<String>) -> Result<Option<CustomerClass>, String> {
+    present(value)
+        .map(|value| match value.to_lowercase().as_str() {
+            "residential" | "res" | "r" | "consumer" => Ok(CustomerClass::Residential),
+            "business" | "bus" | "b" | "commercial" => Ok(CustomerClass::Business),
+            _ => Err(format!("class {value:?} isn't residential or business")),
+        })
+        .transpose()
+}
+
+fn parse_status(value: Option<String>) -> Result<Option<SubscriberStatus>, String> {
+    present(value)
+        .map(
+            |value| match value.to_lowercase().replace([' ', '-'], "_").as_str() {
+                "future" => Ok(SubscriberStatus::Future),
+                "in_trial" => Ok(SubscriberStatus::InTrial),
+                "active" => Ok(SubscriberStatus::Active),
+                "non_renewing" => Ok(SubscriberStatus::NonRenewing),
+                "paused" => Ok(SubscriberStatus::Paused),
+                "cancelled" | "canceled" => Ok(SubscriberStatus::Cancelled),
+                _ => Err(format!("status {value:?} isn't recognized")),
+            },
+        )
+        .transpose()
+}
+
+// spreadsheets export dates as YYYY-MM-DD; ChargeBee style timestamps
+// are accepted too
+fn parse_date(field: &str, value: Option<String>) -> Result<Option<i64>, String> {
+    present(value)
+        .map(|value| {
+            value.parse().or_else(|_| {
+                chrono::NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                    .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp())
+                    .map_err(|_| format!("{field} {value:?} isn't a date"))
+            })
+        })
+        .transpose()
+}
+
+impl TryFrom<CsvRow> for Subscriber {
+    type Error = String;
+
+    fn try_from(row: CsvRow) -> Result<Self, String> {
+        let subscriber = Subscriber {
+            subscription_id: present(row.subscription_id),
+            customer_id: present(row.customer_id),
+            plan_id: present(row.plan_id),
+            service_address: present(row.service_address),
+            service_city_st_zip: present(row.service_city_st_zip),
+            census_block: present(row.census_block),
+            latitude: parse("latitude", row.latitude)?,
+            longitude: parse("longitude", row.longitude)?,
+            class: parse_class(row.class)?,
+            status: parse_status(row.status)?,
+            started_at: parse_date("started_at", row.started_at)?,
+            activated_at: parse_date("activated_at", row.activated_at)?,
+            cancelled_at: parse_date("cancelled_at", row.cancelled_at)?,
+            current_term_end: parse_date("current_term_end", row.current_term_end)?,
+            technology_code: parse("technology_code", row.technology_code)?,
+            download_speed: parse("download_speed", row.download_speed)?,
+            upload_speed: parse("upload_speed", row.upload_speed)?,
+        };
+
+        for (field, value) in [
+            ("class", subscriber.class.is_some()),
+            ("service_address", subscriber.service_address.is_some()),
+            (
+                "service_city_st_zip",
+                subscriber.service_city_st_zip.is_some(),
+            ),
+        ] {
+            if !value {
+                return Err(format!("needs a {field}"));
+            }
+        }
+
+        match (
+            subscriber.technology_code,
+            subscriber.download_speed,
+            subscriber.upload_speed,
+        ) {
+            (None, None, None) if subscriber.plan_id.is_some() => {}
+            (None, None, None) => {
+                return Err(
+                    "needs a plan_id or technology_code, download_speed and upload_speed"
+                        .to_string(),
+                )
+            }
+            (Some(technology_code), Some(download), Some(upload)) => {
+                if ServiceMedium::from_technology_code(technology_code).is_none() {
+                    return Err(format!(
+                        "technology_code {technology_code} isn't one we report"
+                    ));
+                }
+
+                if download == 0 || upload == 0 {
+                    return Err("download_speed and upload_speed must be positive".to_string());
+                }
+            }
+            _ => {
+                return Err(
+                    "technology_code, download_speed and upload_speed go together".to_string(),
+                )
+            }
+        }
+
+        Ok(subscriber)
+    }
+}
+
+// A subscriber export, typically from a spreadsheet. The header row names
+// the columns; mapping says which of them feed which Subscriber fields.
+// The first bad row fails the import so a half-read file is never filed.
+pub struct CsvSource {
+    pub name: String,
+    pub path: String,
+    pub mapping: ColumnMapping,
+}
+
+impl SubscriberSource for CsvSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        self.mapping.validate()?;
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(&self.path)?;
+        let headers = self.mapping.field_headers(reader.headers()?)?;
+
+        let mut subscribers = vec![];
+
+        for record in reader.records() {
+            let record = record?;
+            let line = record
+                .position()
+                .map(|position| position.line())
+                .unwrap_or(0);
+            let row: CsvRow = record.deserialize(Some(&headers))?;
+
+            subscribers.push(
+                Subscriber::try_from(row).map_err(|message| SourceError::Row { line, message })?,
+            );
+        }
+
+        Ok(subscribers)
+    }
+}
+
+// several sources linked as one, e.g. ChargeBee plus an acquired customer
+// base that only exists in a spreadsheet
+pub struct MergedSource {
+    name: String,
+    sources: Vec<Box<dyn SubscriberSource>>,
+}
+
+impl MergedSource {
+    pub fn new(sources: Vec<Box<dyn SubscriberSource>>) -> Self {
+        MergedSource {
+            name: sources
+                .iter()
+                .map(|source| source.name())
+                .collect::<Vec<&str>>()
+                .join(" + "),
+            sources,
+        }
+    }
+}
+
+impl SubscriberSource for MergedSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn snapshot_id(&self) -> Option<String> {
+        self.sources.iter().find_map(|source| source.snapshot_id())
+    }
+
+    fn replays_snapshot(&self) -> bool {
+        self.sources.iter().any(|source| source.replays_snapshot())
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        let mut subscribers = vec![];
+
+        for source in &self.sources {
+            subscribers.append(&mut source.subscribers()?);
+        }
+
+        Ok(subscribers)
+    }
+}
+
+// a JSON array of Subscriber records
+pub struct JsonSnapshotSource {
+    pub path: String,
+}
+
+impl SubscriberSource for JsonSnapshotSource {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn subscribers(&self) -> Result<Vec<Subscriber>, SourceError> {
+        let file = File::open(&self.path)?;
+
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    fn date(text: &str) -> i64 {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .unwrap()
+            .and_time(chrono::NaiveTime::MIN)
+            .and_utc()
+            .timestamp()
+    }
+
+    fn june_30() -> i64 {
+        cutoff(NaiveDate::from_ymd_opt(2024, 6, 30).unwrap())
+    }
+
+    #[test]
+    fn cutoff_is_the_start_of_the_next_day() {
+        assert_eq!(june_30(), date("2024-07-01"));
+    }
+
+    #[test]
+    fn subscribed_on_needs_a_start_before_the_cutoff() {
+        let subscriber = Subscriber {
+            started_at: Some(date("2024-06-30")),
+            ..Subscriber::default()
+        };
+        assert!(subscriber.subscribed_on(june_30()));
+
+        let subscriber = Subscriber {
+            started_at: Some(date("2024-07-01")),
+            ..Subscriber::default()
+        };
+        assert!(!subscriber.subscribed_on(june_30()));
+
+        // activated_at stands in for a missing started_at
+        let subscriber = Subscriber {
+            activated_at: Some(date("2024-07-02")),
+            ..Subscriber::default()
+        };
+        assert!(!subscriber.subscribed_on(june_30()));
+    }
+
+    #[test]
+    fn subscribed_on_without_dates_goes_by_status() {
+        assert!(Subscriber::default().subscribed_on(june_30()));
+
+        for (status, subscribed) in [
+            (SubscriberStatus::Active, true),
+            (SubscriberStatus::Paused, true),
+            (SubscriberStatus::Future, false),
+            (SubscriberStatus::Cancelled, false),
+        ] {
+            let subscriber = Subscriber {
+                status: Some(status),
+                ..Subscriber::default()
+            };
+            assert_eq!(
+                subscriber.subscribed_on(june_30()),
+                subscribed,
+                "{status:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn subscribed_on_counts_cancellations_after_the_cutoff() {
+        let cancelled = |at: &str| Subscriber {
+            status: Some(SubscriberStatus::Cancelled),
+            started_at: Some(date("2023-01-15")),
+            cancelled_at: Some(date(at)),
+            ..Subscriber::default()
+        };
+
+        assert!(cancelled("2024-07-01").subscribed_on(june_30()));
+        assert!(!cancelled("2024-06-30").subscribed_on(june_30()));
+    }
+
+    #[test]
+    fn parse_date_reads_dates_and_timestamps() {
+        assert_eq!(
+            parse_date("started_at", Some("2024-06-30".to_string())),
+            Ok(Some(date("2024-06-30")))
+        );
+        assert_eq!(
+            parse_date("started_at", Some("1719705600".to_string())),
+            Ok(Some(1719705600))
+        );
+        assert_eq!(parse_date("started_at", Some(String::new())), Ok(None));
+        assert_eq!(parse_date("started_at", None), Ok(None));
+    }
+
+    #[test]
+    fn parse_date_rejects_anything_else() {
+        assert_eq!(
+            parse_date("started_at", Some("06/30/2024".to_string())),
+            Err("started_at \"06/30/2024\" isn't a date".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_class_reads_either_class() {
+        for value in ["Residential", "res", "R", "consumer"] {
+            assert_eq!(
+                parse_class(Some(value.to_string())),
+                Ok(Some(CustomerClass::Residential))
+            );
+        }
+        for value in ["BUSINESS", "bus", "b", "Commercial"] {
+            assert_eq!(
+                parse_class(Some(value.to_string())),
+                Ok(Some(CustomerClass::Business))
+            );
+        }
+        assert_eq!(parse_class(Some(String::new())), Ok(None));
+    }
+
+    #[test]
+    fn parse_class_rejects_anything_else() {
+        assert_eq!(
+            parse_class(Some("government".to_string())),
+            Err("class \"government\" isn't residential or business".to_string())
+        );
+    }
+}
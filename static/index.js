@@ -57,9 +57,87 @@ const retrieve = async (event) => {
     });
 };
 
+const retrieveRuns = async () => {
+    fetch("/runs").then((response) => {
+        response.json().then((runs) => {
+            const runsEl = document.getElementById("runs");
+            runsEl.innerText = '';
+
+            runs.filter(run => run.status !== "complete").forEach(run => {
+                const entry = document.createElement('tr');
+
+                const statusCell = document.createElement('td');
+                statusCell.appendChild(document.createTextNode(run.status));
+
+                const timeCell = document.createElement('td');
+                timeCell.appendChild(document.createTextNode(
+                    `${new Date(run.started_at * 1000).toISOString()}`));
+
+                const errorCell = document.createElement('td');
+                errorCell.appendChild(document.createTextNode(run.error || ''));
+
+                entry.appendChild(statusCell);
+                entry.appendChild(timeCell);
+                entry.appendChild(errorCell);
+
+                runsEl.appendChild(entry);
+            });
+        });
+    });
+};
+
+const retrieveReleases = async () => {
+    fetch("/fabric").then((response) => {
+        response.json().then((releases) => {
+            const releaseEl = document.getElementById("release");
+            const selected = releaseEl.value;
+
+            while (releaseEl.options.length > 1) {
+                releaseEl.remove(1);
+            }
+
+            releases.forEach(release => {
+                const option = document.createElement('option');
+                option.value = release.id;
+                option.appendChild(document.createTextNode(
+                    `${release.release_date} (${release.location_count} locations)`));
+                releaseEl.appendChild(option);
+            });
+
+            releaseEl.value = selected;
+        });
+    });
+};
+
+const retrieveSnapshots = async () => {
+    fetch("/snapshots").then((response) => {
+        response.json().then((snapshots) => {
+            const snapshotEl = document.getElementById("snapshot");
+            const selected = snapshotEl.value;
+
+            while (snapshotEl.options.length > 1) {
+                snapshotEl.remove(1);
+            }
+
+            snapshots.forEach(snapshot => {
+                const option = document.createElement('option');
+                option.value = snapshot.id;
+                option.appendChild(document.createTextNode(
+                    `${new Date(snapshot.saved_at * 1000).toLocaleString()} (${snapshot.id.slice(0, 8)})`));
+                snapshotEl.appendChild(option);
+            });
+
+            snapshotEl.value = selected;
+        });
+    });
+};
+
 addEventListener("load", () => {
+    retrieveReleases();
+    retrieveSnapshots();
     poll(() => new Promise(() => {
         retrieve();
+        retrieveRuns();
     }), 10000);
 });
 
@@ -89,6 +167,7 @@ document.addEventListener('submit', (event) => {
         body: new FormData(form),
     }).then(() => {
         form.reset();
+        setTimeout(retrieveReleases, 10000);
     })
 
     event.preventDefault();